@@ -0,0 +1,226 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use anyhow::Result;
+use clap::{App, Arg};
+use futures::{FutureExt, StreamExt};
+use neato_driver::{
+    bridge::{BatteryData, Bridge, LaserScanData, OdometryData},
+    geometry::Pose2D,
+    guard::{self, RobotGuard},
+    DSeries,
+};
+use r2r::{
+    builtin_interfaces::msg::Time,
+    geometry_msgs::msg::{
+        Point, Pose, PoseWithCovariance, Quaternion, Transform, TransformStamped, Twist,
+        TwistWithCovariance, Vector3,
+    },
+    nav_msgs::msg::Odometry,
+    sensor_msgs::msg::{BatteryState, LaserScan},
+    std_msgs::msg::Header,
+    tf2_msgs::msg::TFMessage,
+    Clock, ClockType, QosProfile,
+};
+use serialport::SerialPortSettings;
+
+const ODOM_FRAME: &str = "odom";
+const BASE_FRAME: &str = "base_link";
+const LASER_FRAME: &str = "base_laser_link";
+
+// From sensor_msgs/BatteryState
+const POWER_SUPPLY_STATUS_CHARGING: u8 = 1;
+const POWER_SUPPLY_STATUS_DISCHARGING: u8 = 2;
+const POWER_SUPPLY_STATUS_NOT_CHARGING: u8 = 3;
+const POWER_SUPPLY_TECHNOLOGY_NIMH: u8 = 1;
+
+fn header(stamp: &Time, frame_id: &str) -> Header {
+    Header {
+        stamp: stamp.clone(),
+        frame_id: frame_id.to_string(),
+    }
+}
+
+fn quaternion(yaw: f32) -> Quaternion {
+    let (sin, cos) = (yaw as f64 / 2.0).sin_cos();
+    Quaternion {
+        x: 0.0,
+        y: 0.0,
+        z: sin,
+        w: cos,
+    }
+}
+
+fn transform(stamp: &Time, parent: &str, child: &str, pose: &Pose2D) -> TransformStamped {
+    TransformStamped {
+        header: header(stamp, parent),
+        child_frame_id: child.to_string(),
+        transform: Transform {
+            translation: Vector3 {
+                x: pose.x as f64,
+                y: pose.y as f64,
+                z: 0.0,
+            },
+            rotation: quaternion(pose.theta),
+        },
+    }
+}
+
+fn laser_scan(stamp: &Time, scan: LaserScanData) -> LaserScan {
+    LaserScan {
+        header: header(stamp, LASER_FRAME),
+        angle_min: scan.angle_min,
+        angle_max: scan.angle_max,
+        angle_increment: scan.angle_increment,
+        range_min: scan.range_min,
+        range_max: scan.range_max,
+        ranges: scan.ranges,
+        ..Default::default()
+    }
+}
+
+fn odometry(stamp: &Time, odometry: &OdometryData) -> Odometry {
+    Odometry {
+        header: header(stamp, ODOM_FRAME),
+        child_frame_id: BASE_FRAME.to_string(),
+        pose: PoseWithCovariance {
+            pose: Pose {
+                position: Point {
+                    x: odometry.pose.x as f64,
+                    y: odometry.pose.y as f64,
+                    z: 0.0,
+                },
+                orientation: quaternion(odometry.pose.theta),
+            },
+            ..Default::default()
+        },
+        twist: TwistWithCovariance {
+            twist: Twist {
+                linear: Vector3 {
+                    x: odometry.linear_velocity as f64,
+                    ..Default::default()
+                },
+                angular: Vector3 {
+                    z: odometry.angular_velocity as f64,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        },
+    }
+}
+
+fn battery_state(stamp: &Time, battery: &BatteryData) -> BatteryState {
+    let power_supply_status = if battery.charging {
+        POWER_SUPPLY_STATUS_CHARGING
+    } else if battery.external_power {
+        POWER_SUPPLY_STATUS_NOT_CHARGING
+    } else {
+        POWER_SUPPLY_STATUS_DISCHARGING
+    };
+    BatteryState {
+        header: header(stamp, BASE_FRAME),
+        voltage: battery.voltage,
+        temperature: battery.temperature,
+        current: f32::NAN,
+        charge: f32::NAN,
+        capacity: f32::NAN,
+        design_capacity: f32::NAN,
+        percentage: battery.percentage,
+        power_supply_status,
+        power_supply_technology: POWER_SUPPLY_TECHNOLOGY_NIMH,
+        present: true,
+        ..Default::default()
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = App::new("Neato ROS 2 bridge")
+        .author("Loy van Beek <loy.vanbeek@mail.com>")
+        .about("Publishes scans, odometry and battery state of a Neato and drives it from cmd_vel")
+        .arg(
+            Arg::with_name("device")
+                .short("d")
+                .long("device")
+                .help("Serial port device to use")
+                .default_value("/dev/ttyACM0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("baudrate")
+                .help("Baud-rate with which to communicate over the serial port")
+                .short("b")
+                .long("baudrate")
+                .default_value("115200"),
+        )
+        .get_matches();
+
+    let port = matches.value_of("device").unwrap();
+    let baudrate: u32 = matches.value_of("baudrate").unwrap().parse::<u32>()?;
+
+    env_logger::init();
+
+    let settings = SerialPortSettings {
+        baud_rate: baudrate,
+        timeout: Duration::from_secs(1),
+        ..Default::default()
+    };
+    let comms = serialport::open_with_settings(port, &settings)?;
+
+    let context = r2r::Context::create()?;
+    let mut node = r2r::Node::create(context, "neato_driver", "")?;
+    let scan_publisher = node.create_publisher::<LaserScan>("scan", QosProfile::default())?;
+    let odom_publisher = node.create_publisher::<Odometry>("odom", QosProfile::default())?;
+    let battery_publisher =
+        node.create_publisher::<BatteryState>("battery_state", QosProfile::default())?;
+    let tf_publisher = node.create_publisher::<TFMessage>("/tf", QosProfile::default())?;
+    let mut cmd_vel = node.subscribe::<Twist>("cmd_vel", QosProfile::default())?;
+    let mut clock = Clock::create(ClockType::RosTime)?;
+
+    let running = guard::shutdown_flag()?;
+    let mut bridge = Bridge::new(RobotGuard::new(DSeries::new(comms)));
+    let mut result = bridge.start();
+    let laser_pose = bridge.laser_pose();
+
+    let mut step = || -> Result<()> {
+        node.spin_once(Duration::from_millis(0));
+        let mut latest = None;
+        while let Some(Some(twist)) = cmd_vel.next().now_or_never() {
+            latest = Some(twist);
+        }
+        if let Some(twist) = latest {
+            bridge.drive(twist.linear.x as f32, twist.angular.z as f32)?;
+        }
+
+        let update = match bridge.update() {
+            Ok(update) => update,
+            Err(err) => {
+                log::error!("Could not update from robot: {:?}", err);
+                return Ok(());
+            }
+        };
+        let stamp = Clock::to_builtin_time(&clock.get_now()?);
+
+        tf_publisher.publish(&TFMessage {
+            transforms: vec![
+                transform(&stamp, ODOM_FRAME, BASE_FRAME, &update.odometry.pose),
+                transform(&stamp, BASE_FRAME, LASER_FRAME, &laser_pose),
+            ],
+        })?;
+        odom_publisher.publish(&odometry(&stamp, &update.odometry))?;
+        scan_publisher.publish(&laser_scan(&stamp, update.scan))?;
+        if let Some(battery) = &update.battery {
+            battery_publisher.publish(&battery_state(&stamp, battery))?;
+        }
+        Ok(())
+    };
+
+    while result.is_ok() && running.load(Ordering::SeqCst) {
+        result = step();
+    }
+    drop(step);
+
+    // The guard also stops the robot on a panic, this reports whether stopping worked
+    log::info!("Stopping robot");
+    let stopped = bridge.stop();
+    result.and(stopped.map(|_robot| ()))
+}
@@ -0,0 +1,343 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::{
+    geometry::Pose2D,
+    guard::RobotGuard,
+    lds::{RotationDirection, ScanProjector},
+    odometry::{WheelOdometry, WHEEL_BASE},
+    NeatoRobot, Toggle,
+};
+
+/// Fastest wheel speed the firmware accepts, in mm/s.
+pub const MAX_SPEED: i32 = 300;
+
+/// A motor command covers about a second of driving, so an unchanged command is resent this often.
+const COMMAND_REFRESH: Duration = Duration::from_millis(500);
+
+/// Arguments for `set_motors`: distances in mm and speed in mm/s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MotorCommand {
+    pub left_distance: i32,
+    pub right_distance: i32,
+    pub speed: i32,
+}
+
+impl MotorCommand {
+    /// Drive for about a second at the given velocity, in m/s and rad/s,
+    /// scaled down so neither wheel exceeds `MAX_SPEED`.
+    pub fn from_velocity(linear: f32, angular: f32) -> Self {
+        let mut forward = linear * 1000.0; // meters to millimeters
+        let mut turn = angular * WHEEL_BASE * 1000.0 / 2.0;
+        let fastest = (forward - turn).abs().max((forward + turn).abs());
+        if fastest > MAX_SPEED as f32 {
+            forward *= MAX_SPEED as f32 / fastest;
+            turn *= MAX_SPEED as f32 / fastest;
+        }
+
+        let left_distance = (forward - turn) as i32;
+        let right_distance = (forward + turn) as i32;
+        Self {
+            left_distance,
+            right_distance,
+            speed: left_distance.abs().max(right_distance.abs()),
+        }
+    }
+}
+
+/// Contents of a `sensor_msgs/LaserScan`.
+#[derive(Debug, Clone)]
+pub struct LaserScanData {
+    pub angle_min: f32,
+    pub angle_max: f32,
+    pub angle_increment: f32,
+    pub range_min: f32,
+    pub range_max: f32,
+    pub ranges: Vec<f32>,
+}
+
+impl LaserScanData {
+    /// Reading `i` of `ranges` is at `i` degrees, as in `ScanProjector::beam_angle`.
+    pub fn new(ranges: Vec<f32>, projector: &ScanProjector) -> Self {
+        let count = ranges.len().max(1);
+        let angle_increment = match projector.mount.direction {
+            RotationDirection::CounterClockwise => 1f32.to_radians(),
+            RotationDirection::Clockwise => -1f32.to_radians(),
+        };
+        Self {
+            angle_min: 0.0,
+            angle_max: angle_increment * (count - 1) as f32,
+            angle_increment,
+            range_min: projector.min_range,
+            range_max: projector.max_range,
+            ranges,
+        }
+    }
+}
+
+/// Contents of a `nav_msgs/Odometry` and the odom to base_link transform.
+#[derive(Debug, Clone, Copy)]
+pub struct OdometryData {
+    pub pose: Pose2D,
+    /// Forward velocity in m/s.
+    pub linear_velocity: f32,
+    /// Turn rate in rad/s.
+    pub angular_velocity: f32,
+}
+
+/// Contents of a `sensor_msgs/BatteryState`.
+#[derive(Debug, Clone, Copy)]
+pub struct BatteryData {
+    pub voltage: f32,
+    /// Charge between 0 and 1.
+    pub percentage: f32,
+    /// Degrees Celsius.
+    pub temperature: f32,
+    pub charging: bool,
+    pub external_power: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeUpdate {
+    pub scan: LaserScanData,
+    pub odometry: OdometryData,
+    /// Only polled every `battery_interval` updates.
+    pub battery: Option<BatteryData>,
+}
+
+/// The middleware-independent part of a ROS bridge: polls the robot and drives it.
+pub struct Bridge<R: NeatoRobot> {
+    robot: RobotGuard<R>,
+    pub projector: ScanProjector,
+    pub battery_interval: u32,
+    odometry: WheelOdometry,
+    last_update: Option<Instant>,
+    last_command: Option<(MotorCommand, Instant)>,
+    updates_until_battery: u32,
+}
+
+impl<R: NeatoRobot> Bridge<R> {
+    /// The guard stops the robot and leaves test mode when the bridge is dropped
+    /// without `stop`, such as on a panic.
+    pub fn new(robot: RobotGuard<R>) -> Self {
+        Self {
+            robot,
+            projector: ScanProjector::default(),
+            battery_interval: 10,
+            odometry: WheelOdometry::default(),
+            last_update: None,
+            last_command: None,
+            updates_until_battery: 0,
+        }
+    }
+
+    pub fn robot(&mut self) -> &mut R {
+        &mut self.robot
+    }
+
+    /// Put the robot in test mode and spin up the LDS.
+    pub fn start(&mut self) -> Result<()> {
+        self.robot.set_testmode(Toggle::On)?;
+        self.robot.set_ldsrotation(Toggle::On)?;
+        Ok(())
+    }
+
+    /// Stop the robot and hand it back.
+    pub fn stop(self) -> Result<R> {
+        self.robot.release()
+    }
+
+    /// Pose of the LDS on the robot, for the base_link to laser transform.
+    pub fn laser_pose(&self) -> Pose2D {
+        let mount = self.projector.mount;
+        Pose2D::new(mount.x, mount.y, mount.yaw)
+    }
+
+    pub fn update(&mut self) -> Result<BridgeUpdate> {
+        self.robot.request_scan()?;
+        let ranges = self.robot.get_scan_ranges()?;
+        let motors = self.robot.get_motors()?;
+        let now = Instant::now();
+
+        let delta = self.odometry.update(&motors);
+        let (linear_velocity, angular_velocity) = match self.last_update {
+            Some(last) if now > last => {
+                let seconds = (now - last).as_secs_f32();
+                (
+                    delta.x.hypot(delta.y).copysign(delta.x) / seconds,
+                    delta.theta / seconds,
+                )
+            }
+            _ => (0.0, 0.0),
+        };
+        self.last_update = Some(now);
+
+        let battery = if self.updates_until_battery == 0 {
+            self.updates_until_battery = self.battery_interval.saturating_sub(1);
+            let charger = self.robot.get_charger()?;
+            Some(BatteryData {
+                voltage: charger.v_batt_v_v,
+                percentage: charger.fuel_percent as f32 / 100.0,
+                temperature: charger.batt_temp_c_avg as f32,
+                charging: charger.charging_active != 0,
+                external_power: charger.ext_pwr_present != 0,
+            })
+        } else {
+            self.updates_until_battery -= 1;
+            None
+        };
+
+        Ok(BridgeUpdate {
+            scan: LaserScanData::new(ranges, &self.projector),
+            odometry: OdometryData {
+                pose: self.odometry.pose(),
+                linear_velocity,
+                angular_velocity,
+            },
+            battery,
+        })
+    }
+
+    /// Drive at a `cmd_vel` velocity, in m/s and rad/s.
+    pub fn drive(&mut self, linear: f32, angular: f32) -> Result<()> {
+        let command = MotorCommand::from_velocity(linear, angular);
+        let now = Instant::now();
+        let resend = match self.last_command {
+            Some((last, sent)) => last != command || now - sent >= COMMAND_REFRESH,
+            None => true,
+        };
+        if resend {
+            self.robot
+                .set_motors(command.left_distance, command.right_distance, command.speed)?;
+            self.last_command = Some((command, now));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries};
+
+    /// A `GetLDSScan` reply with every reading at `distance_mm`.
+    fn scan_reply(distance_mm: u32) -> String {
+        let mut reply =
+            String::from("getldsscan\r\nAngleInDegrees,DistInMM,Intensity,ErrorCodeHEX\r\n");
+        for angle in 0..360 {
+            reply.push_str(&format!("{},{},100,0\r\n", angle, distance_mm));
+        }
+        reply.push_str("ROTATION_SPEED,5.00\r\n\x1a");
+        reply
+    }
+
+    fn motors_reply(left_mm: i32, right_mm: i32) -> String {
+        format!(
+            "getmotors\r\nParameter,Value\r\nBrush_RPM,0\r\nBrush_mA,0\r\nVacuum_RPM,0\r\n\
+             Vacuum_mA,0\r\nLeftWheel_RPM,0\r\nLeftWheel_Load%,0\r\n\
+             LeftWheel_PositionInMM,{}\r\nLeftWheel_Speed,0\r\nRightWheel_RPM,0\r\n\
+             RightWheel_Load%,0\r\nRightWheel_PositionInMM,{}\r\nRightWheel_Speed,0\r\n\
+             SideBrush_mA,0\r\n\x1a",
+            left_mm, right_mm
+        )
+    }
+
+    const CHARGER_REPLY: &str = "getcharger\r\nLabel,Value\r\nFuelPercent,80\r\n\
+        BatteryOverTemp,0\r\nChargingActive,1\r\nChargingEnabled,1\r\nConfidentOnFuel,0\r\n\
+        OnReservedFuel,0\r\nEmptyFuel,0\r\nBatteryFailure,0\r\nExtPwrPresent,1\r\n\
+        ThermistorPresent,1\r\nBattTempCAvg,31\r\nVBattV,16.25\r\nVExtV,22.62\r\n\
+        Charger_mAH,0\r\nDischarge_mAH,208\r\n\x1a";
+
+    /// What `Bridge::stop` sends.
+    fn expect_stop(port: ScriptedPort) -> ScriptedPort {
+        port.expect("setmotor 0 0 0", "setmotor 0 0 0\r\n")
+            .expect("setldsrotation off", "setldsrotation off\r\n")
+            .expect("testmode off", "testmode off\r\n")
+    }
+
+    fn bridge(port: &ScriptedPort) -> Bridge<DSeries<'static>> {
+        Bridge::new(RobotGuard::new(DSeries::new(Box::new(port.clone()))))
+    }
+
+    #[test]
+    fn publishes_scan_odometry_and_battery() {
+        let port = ScriptedPort::new()
+            .expect("getldsscan", &scan_reply(1000))
+            .expect("getmotors", &motors_reply(0, 0))
+            .expect("getcharger", CHARGER_REPLY)
+            .expect("getldsscan", &scan_reply(900))
+            .expect("getmotors", &motors_reply(100, 100));
+        let port = expect_stop(port);
+        let mut bridge = bridge(&port);
+
+        let first = bridge.update().unwrap();
+        assert_eq!(first.scan.ranges, vec![1.0; 360]);
+        assert_eq!(first.scan.angle_min, 0.0);
+        assert!((first.scan.angle_increment - 1f32.to_radians()).abs() < 1e-6);
+        assert!((first.scan.angle_max - 359f32.to_radians()).abs() < 1e-5);
+        assert_eq!(first.odometry.pose, Pose2D::default());
+        assert_eq!(first.odometry.linear_velocity, 0.0);
+
+        let battery = first.battery.unwrap();
+        assert_eq!(battery.voltage, 16.25);
+        assert_eq!(battery.percentage, 0.8);
+        assert_eq!(battery.temperature, 31.0);
+        assert!(battery.charging);
+        assert!(battery.external_power);
+
+        let second = bridge.update().unwrap();
+        assert_eq!(second.scan.ranges, vec![0.9; 360]);
+        assert!((second.odometry.pose.x - 0.1).abs() < 1e-6);
+        assert_eq!(second.odometry.pose.y, 0.0);
+        assert_eq!(second.odometry.pose.theta, 0.0);
+        assert!(second.odometry.linear_velocity > 0.0);
+        assert_eq!(second.odometry.angular_velocity, 0.0);
+        assert!(second.battery.is_none());
+
+        // The transforms: odom to base_link is the odometry pose, base_link to laser the mount
+        let mount = bridge.projector.mount;
+        assert_eq!(
+            bridge.laser_pose(),
+            Pose2D::new(mount.x, mount.y, mount.yaw)
+        );
+
+        bridge.stop().unwrap();
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn cmd_vel_becomes_setmotor() {
+        let turn = (WHEEL_BASE * 1000.0 / 2.0) as i32;
+        let port = ScriptedPort::new()
+            .expect("setmotor 200 200 200", "setmotor 200 200 200\r\n")
+            .expect(
+                &format!("setmotor {} {} {}", -turn, turn, turn),
+                "setmotor\r\n",
+            );
+        let port = expect_stop(port);
+        let mut bridge = bridge(&port);
+
+        bridge.drive(0.2, 0.0).unwrap();
+        // An unchanged command is not resent before it runs out
+        bridge.drive(0.2, 0.0).unwrap();
+        bridge.drive(0.0, 1.0).unwrap();
+        assert_eq!(port.written().len(), 2);
+
+        bridge.stop().unwrap();
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn cmd_vel_is_limited_to_max_speed() {
+        let command = MotorCommand::from_velocity(1.0, 0.0);
+        assert_eq!(
+            command,
+            MotorCommand {
+                left_distance: MAX_SPEED,
+                right_distance: MAX_SPEED,
+                speed: MAX_SPEED,
+            }
+        );
+    }
+}
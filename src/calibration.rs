@@ -0,0 +1,560 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr, thread, time};
+
+use anyhow::Result;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{guard::restore, LdsScan, MotorStatus, NeatoRobot, Toggle};
+
+/// Readings a distance sensor was calibrated with, -1 when not calibrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SensorCalibration {
+    pub min: i32,
+    pub mid: i32,
+    pub max: i32,
+}
+
+impl Default for SensorCalibration {
+    fn default() -> Self {
+        Self {
+            min: -1,
+            mid: -1,
+            max: -1,
+        }
+    }
+}
+
+impl SensorCalibration {
+    pub fn is_calibrated(&self) -> bool {
+        self.min >= 0 && self.mid >= 0 && self.max >= 0
+    }
+}
+
+/// The calibration table from `GetCalInfo`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CalInfo {
+    /// Correction of the LDS angle, as the firmware reports it.
+    pub lds_offset: i32,
+    pub left_drop: SensorCalibration,
+    pub right_drop: SensorCalibration,
+    pub wall: SensorCalibration,
+    /// The firmware's wheel distance correction, on firmware that reports one.
+    pub wheel_distance_scale: Option<f32>,
+    /// Every other parameter, such as the accelerometer offsets, by name.
+    pub other: BTreeMap<String, String>,
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid calibration line {0:?}")]
+pub struct InvalidCalInfoError(String);
+
+impl FromStr for CalInfo {
+    type Err = InvalidCalInfoError;
+
+    /// Parse a `GetCalInfo` reply of `Parameter,Value` lines, such as `RDropMin,-1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut info = CalInfo::default();
+
+        for line in s
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| line.contains(','))
+        {
+            let invalid = || InvalidCalInfoError(line.to_string());
+            let mut fields = line.splitn(2, ',').map(|field| field.trim());
+            let name = fields.next().unwrap_or("");
+            let value = fields.next().unwrap_or("");
+            let int = || value.parse::<i32>().map_err(|_| invalid());
+
+            match name.to_lowercase().as_str() {
+                "parameter" => continue,
+                "ldsoffset" => info.lds_offset = int()?,
+                "ldropmin" => info.left_drop.min = int()?,
+                "ldropmid" => info.left_drop.mid = int()?,
+                "ldropmax" => info.left_drop.max = int()?,
+                "rdropmin" => info.right_drop.min = int()?,
+                "rdropmid" => info.right_drop.mid = int()?,
+                "rdropmax" => info.right_drop.max = int()?,
+                "wallmin" => info.wall.min = int()?,
+                "wallmid" => info.wall.mid = int()?,
+                "wallmax" => info.wall.max = int()?,
+                "wheeldistancescale" => {
+                    info.wheel_distance_scale = Some(value.parse().map_err(|_| invalid())?)
+                }
+                _ => {
+                    info.other.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// The readings `SetDistanceCal` can take, each with the calibration target at
+/// the matching distance in front of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceCalPoint {
+    DropMinimum,
+    DropMiddle,
+    DropMaximum,
+    WallMinimum,
+    WallMiddle,
+    WallMaximum,
+}
+
+impl Display for DistanceCalPoint {
+    /// The argument of `SetDistanceCal` for this reading.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let argument = match self {
+            DistanceCalPoint::DropMinimum => "dropminimum",
+            DistanceCalPoint::DropMiddle => "dropmiddle",
+            DistanceCalPoint::DropMaximum => "dropmaximum",
+            DistanceCalPoint::WallMinimum => "wallminimum",
+            DistanceCalPoint::WallMiddle => "wallmiddle",
+            DistanceCalPoint::WallMaximum => "wallmaximum",
+        };
+        write!(f, "{}", argument)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown distance calibration reading {0:?}")]
+pub struct UnknownDistanceCalPointError(String);
+
+impl FromStr for DistanceCalPoint {
+    type Err = UnknownDistanceCalPointError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "dropminimum" => Ok(DistanceCalPoint::DropMinimum),
+            "dropmiddle" => Ok(DistanceCalPoint::DropMiddle),
+            "dropmaximum" => Ok(DistanceCalPoint::DropMaximum),
+            "wallminimum" => Ok(DistanceCalPoint::WallMinimum),
+            "wallmiddle" => Ok(DistanceCalPoint::WallMiddle),
+            "wallmaximum" => Ok(DistanceCalPoint::WallMaximum),
+            _ => Err(UnknownDistanceCalPointError(s.to_string())),
+        }
+    }
+}
+
+/// How `calibrate_wheel_distance` drives towards the wall.
+#[derive(Debug, Clone, Copy)]
+pub struct WheelCalibrationConfig {
+    /// Distance to drive towards the wall, in mm.
+    pub distance_mm: i32,
+    /// In mm/s.
+    pub speed: i32,
+    /// Distance to keep from the wall at the end of the drive, in mm.
+    pub clearance_mm: f32,
+    /// Scans to take the median wall distance of, before and after driving.
+    pub scans: usize,
+    /// Readings up to this many degrees from straight ahead hit the wall.
+    pub half_angle: u16,
+    /// Polls of the wheel positions without change after which the robot has stopped.
+    pub settle_count: usize,
+    pub poll_interval: time::Duration,
+}
+
+impl Default for WheelCalibrationConfig {
+    fn default() -> Self {
+        Self {
+            distance_mm: 500,
+            speed: 100,
+            clearance_mm: 200.0,
+            scans: 5,
+            half_angle: 3,
+            settle_count: 3,
+            poll_interval: time::Duration::from_millis(100),
+        }
+    }
+}
+
+/// The outcome of `calibrate_wheel_distance`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WheelCalibration {
+    pub wall_before_mm: f32,
+    pub wall_after_mm: f32,
+    /// Distance the wheel encoders reported, the mean of both wheels.
+    pub wheel_distance_mm: f32,
+    /// Multiply the distance the wheels report with this to get the distance driven,
+    /// see `WheelOdometry::set_distance_scale`.
+    pub scale: f32,
+}
+
+#[derive(Error, Debug)]
+pub enum CalibrationError {
+    #[error("No wall within {half_angle} degrees of straight ahead")]
+    NoWall { half_angle: u16 },
+    #[error("Wall is {distance_mm} mm ahead, but driving needs {needed_mm} mm")]
+    TooClose { distance_mm: f32, needed_mm: f32 },
+    #[error("The wheels reported no motion")]
+    NoMotion,
+}
+
+/// Median distance to the wall straight ahead, from the valid readings of `scans`
+/// up to `half_angle` degrees from the front.
+pub fn wall_distance(scans: &[LdsScan], half_angle: u16) -> Option<f32> {
+    let mut distances: Vec<u32> = scans
+        .iter()
+        .flat_map(|scan| scan.readings.iter())
+        .filter(|reading| reading.is_valid() && reading.distance_mm > 0)
+        .filter(|reading| {
+            let angle = reading.angle % 360;
+            angle.min(360 - angle) <= half_angle
+        })
+        .map(|reading| reading.distance_mm)
+        .collect();
+    if distances.is_empty() {
+        return None;
+    }
+    distances.sort_unstable();
+    Some(distances[distances.len() / 2] as f32)
+}
+
+/// Calibrate the wheel distance against the LDS, with the robot facing a flat wall.
+///
+/// The robot measures the distance to the wall, drives straight towards it and
+/// measures again, taking the difference as the distance it really drove. The
+/// firmware has no command to store the result, so apply it to the odometry.
+/// Turns on test mode and the LDS, and leaves them off again.
+pub fn calibrate_wheel_distance<R: NeatoRobot + ?Sized>(
+    robot: &mut R,
+    config: &WheelCalibrationConfig,
+) -> Result<WheelCalibration> {
+    robot.set_testmode(Toggle::On)?;
+    let calibration = measure(robot, config);
+    // Also stops the wheels when measuring failed while driving
+    let restored = restore(robot);
+    let calibration = calibration?;
+    restored?;
+    log::info!("Wheel distance scale {}", calibration.scale);
+    Ok(calibration)
+}
+
+fn measure<R: NeatoRobot + ?Sized>(
+    robot: &mut R,
+    config: &WheelCalibrationConfig,
+) -> Result<WheelCalibration> {
+    robot.set_ldsrotation(Toggle::On)?;
+    let wall_before_mm = measure_wall(robot, config)?;
+    let needed_mm = config.distance_mm as f32 + config.clearance_mm;
+    if wall_before_mm < needed_mm {
+        return Err(CalibrationError::TooClose {
+            distance_mm: wall_before_mm,
+            needed_mm,
+        }
+        .into());
+    }
+
+    let before = robot.get_motors()?;
+    robot.set_motors(config.distance_mm, config.distance_mm, config.speed)?;
+    let after = wait_until_stopped(robot, config)?;
+    let wall_after_mm = measure_wall(robot, config)?;
+
+    let wheel_distance_mm = ((after.left_wheel_position_in_mm - before.left_wheel_position_in_mm)
+        + (after.right_wheel_position_in_mm - before.right_wheel_position_in_mm))
+        as f32
+        / 2.0;
+    if wheel_distance_mm <= 0.0 {
+        return Err(CalibrationError::NoMotion.into());
+    }
+
+    Ok(WheelCalibration {
+        wall_before_mm,
+        wall_after_mm,
+        wheel_distance_mm,
+        scale: (wall_before_mm - wall_after_mm) / wheel_distance_mm,
+    })
+}
+
+fn measure_wall<R: NeatoRobot + ?Sized>(
+    robot: &mut R,
+    config: &WheelCalibrationConfig,
+) -> Result<f32> {
+    let scans = (0..config.scans)
+        .map(|_| robot.get_lds_scan())
+        .collect::<Result<Vec<_>>>()?;
+    let distance = wall_distance(&scans, config.half_angle).ok_or(CalibrationError::NoWall {
+        half_angle: config.half_angle,
+    })?;
+    log::debug!("Wall at {} mm", distance);
+    Ok(distance)
+}
+
+/// Wait for the drive to finish, then poll the motors until the wheel positions
+/// stop changing, returning the last status.
+fn wait_until_stopped<R: NeatoRobot + ?Sized>(
+    robot: &mut R,
+    config: &WheelCalibrationConfig,
+) -> Result<MotorStatus> {
+    let drive_time = time::Duration::from_millis(
+        1000 * config.distance_mm.unsigned_abs() as u64 / config.speed.max(1) as u64,
+    );
+    thread::sleep(drive_time);
+
+    // Give up after as long again as the drive should take
+    let start = time::Instant::now();
+    let mut last = robot.get_motors()?;
+    let mut unchanged = 0;
+    while unchanged < config.settle_count && start.elapsed() < drive_time {
+        thread::sleep(config.poll_interval);
+        let status = robot.get_motors()?;
+        let moved = status.left_wheel_position_in_mm != last.left_wheel_position_in_mm
+            || status.right_wheel_position_in_mm != last.right_wheel_position_in_mm;
+        unchanged = if moved { 0 } else { unchanged + 1 };
+        last = status;
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fields::ParseReplyError, scripted_port::ScriptedPort, CommandError, DSeries, LdsReading,
+        SpinUpConfig,
+    };
+
+    const CAL_INFO: &str = "Parameter,Value\r
+LDSOffset,-12\r
+XAccel,0\r
+YAccel,1\r
+ZAccel,-3\r
+RTCOffset,0\r
+LCDContrast,17\r
+RDropMin,-1\r
+RDropMid,-1\r
+RDropMax,-1\r
+LDropMin,250\r
+LDropMid,420\r
+LDropMax,890\r
+WallMin,-1\r
+WallMid,-1\r
+WallMax,-1\r
+";
+
+    #[test]
+    fn parses_cal_info() {
+        let info: CalInfo = CAL_INFO.parse().unwrap();
+        assert_eq!(info.lds_offset, -12);
+        assert_eq!(
+            info.left_drop,
+            SensorCalibration {
+                min: 250,
+                mid: 420,
+                max: 890
+            }
+        );
+        assert!(info.left_drop.is_calibrated());
+        assert!(!info.right_drop.is_calibrated());
+        assert_eq!(info.wall, SensorCalibration::default());
+        assert_eq!(info.wheel_distance_scale, None);
+        assert_eq!(info.other["LCDContrast"], "17");
+        assert_eq!(info.other.len(), 5);
+    }
+
+    #[test]
+    fn parses_wheel_distance_scale() {
+        let info: CalInfo = "WheelDistanceScale,1.025\r\n".parse().unwrap();
+        assert_eq!(info.wheel_distance_scale, Some(1.025));
+        let info: CalInfo = "WheelScaleOffset,3\r\nDistanceScaleMin,0.9\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(info.wheel_distance_scale, None);
+        assert_eq!(info.other.len(), 2);
+        assert!("LDropMin,low\r\n".parse::<CalInfo>().is_err());
+    }
+
+    #[test]
+    fn formats_and_parses_setdistancecal_arguments() {
+        let points = [
+            (DistanceCalPoint::DropMinimum, "dropminimum"),
+            (DistanceCalPoint::DropMiddle, "dropmiddle"),
+            (DistanceCalPoint::DropMaximum, "dropmaximum"),
+            (DistanceCalPoint::WallMinimum, "wallminimum"),
+            (DistanceCalPoint::WallMiddle, "wallmiddle"),
+            (DistanceCalPoint::WallMaximum, "wallmaximum"),
+        ];
+        for (point, argument) in points.iter() {
+            assert_eq!(point.to_string(), *argument);
+            assert_eq!(argument.parse::<DistanceCalPoint>().unwrap(), *point);
+        }
+        assert_eq!(
+            " WallMiddle ".parse::<DistanceCalPoint>().unwrap(),
+            DistanceCalPoint::WallMiddle
+        );
+        assert!("wall".parse::<DistanceCalPoint>().is_err());
+    }
+
+    #[test]
+    fn setdistancecal_needs_test_mode() {
+        let port = ScriptedPort::new()
+            .expect("testmode on", "testmode on\r\n")
+            .expect(
+                "setdistancecal wallmiddle",
+                "setdistancecal wallmiddle\r\n\x1a",
+            );
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        // Refused without sending anything while the test mode is unknown
+        let err = robot
+            .set_distance_cal(DistanceCalPoint::WallMiddle)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandError>(),
+            Some(CommandError::TestModeOff { .. })
+        ));
+        assert!(port.written().is_empty());
+
+        robot.set_testmode(Toggle::On).unwrap();
+        robot
+            .set_distance_cal(DistanceCalPoint::WallMiddle)
+            .unwrap();
+        assert!(port.is_finished());
+    }
+
+    /// A scan with a wall `distance_mm` straight ahead, nothing else in range,
+    /// and a few invalid readings.
+    fn wall_scan(distance_mm: u32) -> LdsScan {
+        LdsScan {
+            readings: (0..360)
+                .map(|angle| LdsReading {
+                    angle,
+                    distance_mm: match angle {
+                        0..=5 | 355..=359 => distance_mm + angle as u32 % 3,
+                        _ => 5000,
+                    },
+                    intensity: 100,
+                    error_code: if angle == 1 { 0x8035 } else { 0 },
+                })
+                .collect(),
+            rotation_speed: Some(5.0),
+        }
+    }
+
+    fn scan_reply(scan: &LdsScan) -> String {
+        let mut reply =
+            String::from("getldsscan\r\nAngleInDegrees,DistInMM,Intensity,ErrorCodeHEX\r\n");
+        for reading in &scan.readings {
+            reply.push_str(&format!(
+                "{},{},{},{:X}\r\n",
+                reading.angle, reading.distance_mm, reading.intensity, reading.error_code
+            ));
+        }
+        reply.push_str("ROTATION_SPEED,5.00\r\n\x1a");
+        reply
+    }
+
+    fn motors_reply(left_mm: i32, right_mm: i32) -> String {
+        format!(
+            "getmotors\r\nParameter,Value\r\nBrush_RPM,0\r\nVacuum_RPM,0\r\n\
+             LeftWheel_PositionInMM,{}\r\nRightWheel_PositionInMM,{}\r\n\x1a",
+            left_mm, right_mm
+        )
+    }
+
+    #[test]
+    fn wall_distance_is_median_ahead() {
+        // Readings from 357 to 3 degrees, without the invalid one at 1 degree
+        assert_eq!(wall_distance(&[wall_scan(1000)], 3), Some(1001.0));
+        assert_eq!(
+            wall_distance(&[wall_scan(1000), wall_scan(2000), wall_scan(2000)], 3),
+            Some(2000.0)
+        );
+        // Wider, more readings miss the wall than hit it
+        assert_eq!(wall_distance(&[wall_scan(1000)], 10), Some(5000.0));
+        assert_eq!(wall_distance(&[], 3), None);
+
+        let mut nothing = wall_scan(1000);
+        for reading in &mut nothing.readings {
+            reading.error_code = 0x8035;
+        }
+        assert_eq!(wall_distance(&[nothing], 3), None);
+    }
+
+    fn calibration_port(
+        wall_before: u32,
+        script: impl FnOnce(ScriptedPort) -> ScriptedPort,
+    ) -> ScriptedPort {
+        let port = ScriptedPort::new()
+            .expect("testmode on", "testmode on\r\n")
+            .expect("setldsrotation on", "setldsrotation on\r\n")
+            .expect("getldsscan", &scan_reply(&wall_scan(3000)))
+            .expect("getldsscan", &scan_reply(&wall_scan(3000)))
+            .expect("getldsscan", &scan_reply(&wall_scan(wall_before)));
+        script(port)
+            .expect("setmotor 0 0 0", "setmotor 0 0 0\r\n")
+            .expect("setldsrotation off", "setldsrotation off\r\n")
+            .expect("testmode off", "testmode off\r\n")
+    }
+
+    fn calibration_robot(port: &ScriptedPort) -> DSeries<'static> {
+        let mut robot = DSeries::new(Box::new(port.clone()));
+        robot.set_lds_spin_up(SpinUpConfig {
+            settle_count: 2,
+            poll_interval: time::Duration::from_millis(0),
+            ..Default::default()
+        });
+        robot
+    }
+
+    fn calibration_config() -> WheelCalibrationConfig {
+        WheelCalibrationConfig {
+            distance_mm: 100,
+            speed: 1000,
+            scans: 1,
+            settle_count: 1,
+            poll_interval: time::Duration::from_millis(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scale_is_wall_distance_over_wheel_distance() {
+        let port = calibration_port(1000, |port| {
+            port.expect("getmotors", &motors_reply(10, 20))
+                .expect("setmotor 100 100 1000", "setmotor 100 100 1000\r\n")
+                .expect("getmotors", &motors_reply(105, 117))
+                .expect("getmotors", &motors_reply(105, 117))
+                .expect("getldsscan", &scan_reply(&wall_scan(900)))
+        });
+        let mut robot = calibration_robot(&port);
+
+        let calibration = calibrate_wheel_distance(&mut robot, &calibration_config()).unwrap();
+        assert_eq!(calibration.wall_before_mm, 1001.0);
+        assert_eq!(calibration.wall_after_mm, 901.0);
+        // The wheels report 95 and 97 mm, the wall came 100 mm closer
+        assert_eq!(calibration.wheel_distance_mm, 96.0);
+        assert!((calibration.scale - 100.0 / 96.0).abs() < 1e-6);
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn refuses_to_drive_into_a_close_wall() {
+        let port = calibration_port(250, |port| port);
+        let mut robot = calibration_robot(&port);
+
+        let err = calibrate_wheel_distance(&mut robot, &calibration_config()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CalibrationError>(),
+            Some(CalibrationError::TooClose { .. })
+        ));
+        // Still leaves test mode
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn stops_the_wheels_when_measuring_fails() {
+        let port = calibration_port(1000, |port| {
+            port.expect("getmotors", &motors_reply(10, 20))
+                .expect("setmotor 100 100 1000", "setmotor 100 100 1000\r\n")
+                .expect("getmotors", "getmotors\r\nParameter,Value\r\n\x1a")
+        });
+        let mut robot = calibration_robot(&port);
+
+        let err = calibrate_wheel_distance(&mut robot, &calibration_config()).unwrap_err();
+        assert!(err.downcast_ref::<ParseReplyError>().is_some(), "{:?}", err);
+        assert!(port.is_finished());
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use neato_driver::{
+    AnalogSensorStatus, ChargerStatus, DSeries, DigitalSensorStatus, MotorStatus, NeatoRobot,
+    Version,
+};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Commands from the Neato programmer's manual, with the arguments that can be completed.
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("Clean", &["House", "Spot", "Stop"]),
+    ("DiagTest", &[]),
+    ("GetAccel", &[]),
+    ("GetAnalogSensors", &["raw", "stats"]),
+    ("GetButtons", &[]),
+    ("GetCalInfo", &[]),
+    ("GetCharger", &[]),
+    ("GetDigitalSensors", &[]),
+    ("GetErr", &["Clear"]),
+    ("GetLDSScan", &[]),
+    ("GetLifeStatLog", &[]),
+    (
+        "GetMotors",
+        &[
+            "Brush",
+            "Vacuum",
+            "LeftWheel",
+            "RightWheel",
+            "Laser",
+            "Charger",
+        ],
+    ),
+    ("GetSchedule", &["Day"]),
+    ("GetSysLog", &[]),
+    ("GetTime", &[]),
+    ("GetVersion", &[]),
+    ("GetWarranty", &[]),
+    ("Help", &[]),
+    ("PlaySound", &["SoundID", "Stop"]),
+    ("RestoreDefaults", &[]),
+    (
+        "SetDistanceCal",
+        &[
+            "DropMinimum",
+            "DropMiddle",
+            "DropMaximum",
+            "WallMinimum",
+            "WallMiddle",
+            "WallMaximum",
+        ],
+    ),
+    ("SetFuelGauge", &["Percent"]),
+    (
+        "SetLCD",
+        &[
+            "BGWhite", "BGBlack", "HLine", "VLine", "HBars", "VBars", "FGWhite", "FGBlack",
+            "Contrast",
+        ],
+    ),
+    ("SetLDSRotation", &["On", "Off"]),
+    (
+        "SetLED",
+        &[
+            "ButtonAmber",
+            "ButtonGreen",
+            "LEDRed",
+            "LEDGreen",
+            "ButtonAmberDim",
+            "ButtonGreenDim",
+            "ButtonOff",
+            "BacklightOn",
+            "BacklightOff",
+            "BlinkOn",
+            "BlinkOff",
+        ],
+    ),
+    (
+        "SetMotor",
+        &[
+            "LWheelDist",
+            "RWheelDist",
+            "Speed",
+            "Accel",
+            "RPM",
+            "Brush",
+            "VacuumOn",
+            "VacuumOff",
+            "VacuumSpeed",
+            "RWheelDisable",
+            "LWheelDisable",
+            "BrushDisable",
+            "RWheelEnable",
+            "LWheelEnable",
+            "BrushEnable",
+        ],
+    ),
+    (
+        "SetSchedule",
+        &["Day", "Hour", "Min", "House", "None", "ON", "OFF"],
+    ),
+    (
+        "SetSystemMode",
+        &["Shutdown", "Hibernate", "Standby", "PowerCycle"],
+    ),
+    ("SetTime", &["Day", "Hour", "Min", "Sec"]),
+    ("SetWallFollower", &["Enable", "Disable"]),
+    ("TestMode", &["On", "Off"]),
+];
+
+/// Where the word being completed starts in `line`, and the commands or arguments
+/// it can be completed to.
+fn completions(line: &str) -> (usize, Vec<&'static str>) {
+    let start = line.rfind(' ').map_or(0, |space| space + 1);
+    let word = line[start..].to_lowercase();
+
+    let candidates: Vec<&'static str> = match line[..start].split_whitespace().next() {
+        None => COMMANDS.iter().map(|(command, _)| *command).collect(),
+        Some(command) => COMMANDS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(command))
+            .map_or(vec![], |(_, arguments)| arguments.to_vec()),
+    };
+
+    let matches = candidates
+        .into_iter()
+        .filter(|candidate| candidate.to_lowercase().starts_with(&word))
+        .collect();
+    (start, matches)
+}
+
+struct NeatoHelper;
+
+impl Completer for NeatoHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, matches) = completions(&line[..pos]);
+        let pairs = matches
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.to_string(),
+                replacement: format!("{} ", candidate),
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for NeatoHelper {
+    type Hint = String;
+}
+
+impl Highlighter for NeatoHelper {}
+
+impl Validator for NeatoHelper {}
+
+impl Helper for NeatoHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".neato_history"))
+}
+
+/// Pad every column of `rows` to its widest cell.
+fn format_table(rows: &[Vec<String>]) -> Vec<String> {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.len())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        })
+        .collect()
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Rows of a parsed reply: a row per field, or per item for lists.
+fn parsed_rows<T: Serialize>(parsed: &T) -> Result<Vec<Vec<String>>> {
+    let value = serde_json::to_value(parsed)?;
+    let fields = match value {
+        Value::Object(fields) => fields,
+        other => return Ok(vec![vec![cell(&other)]]),
+    };
+
+    let mut rows = vec![];
+    for (name, value) in &fields {
+        match value {
+            Value::Array(items) if items.iter().all(Value::is_object) => {
+                if let Some(Value::Object(first)) = items.first() {
+                    rows.push(first.keys().cloned().collect());
+                }
+                for item in items {
+                    if let Value::Object(item) = item {
+                        rows.push(item.values().map(cell).collect());
+                    }
+                }
+            }
+            value => rows.push(vec![name.clone(), cell(value)]),
+        }
+    }
+    Ok(rows)
+}
+
+/// Parse `body` with the `FromStr` impl of a known reply into rows,
+/// or `None` when it does not parse.
+fn try_parse<T>(body: &str) -> Option<Vec<Vec<String>>>
+where
+    T: FromStr + Serialize,
+    T::Err: Debug,
+{
+    match T::from_str(body) {
+        Ok(parsed) => parsed_rows(&parsed).ok(),
+        Err(err) => {
+            log::debug!("Could not parse reply: {:?}", err);
+            None
+        }
+    }
+}
+
+/// The lines to print for the reply to `command`: a table for replies this crate
+/// parses, otherwise the reply as the robot sent it.
+fn format_reply(command: &str, reply: &str) -> Vec<String> {
+    let lines: Vec<&str> = reply
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    // Skip the header row, the parsers only expect the fields
+    let body = lines
+        .iter()
+        .skip(1)
+        .cloned()
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let name = command
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    let rows = match name.as_str() {
+        "getmotors" if command.split_whitespace().count() == 1 => try_parse::<MotorStatus>(&body),
+        "getanalogsensors" if command.split_whitespace().count() == 1 => {
+            try_parse::<AnalogSensorStatus>(&body)
+        }
+        "getdigitalsensors" => try_parse::<DigitalSensorStatus>(&body),
+        "getcharger" => try_parse::<ChargerStatus>(&body),
+        "getversion" => try_parse::<Version>(reply),
+        _ => None,
+    };
+
+    match rows {
+        Some(rows) => format_table(&rows),
+        None => vec![reply.trim_end().to_string()],
+    }
+}
+
+/// Read commands from the terminal, send them to the robot and print the replies.
+pub fn run(robot: &mut DSeries, running: &Arc<AtomicBool>) -> Result<()> {
+    let mut editor: Editor<NeatoHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(NeatoHelper));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    println!("Type a Neato command, Tab completes, Ctrl-D quits.");
+    while running.load(Ordering::SeqCst) {
+        let line = match editor.readline("neato> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(command)?;
+        if command.eq_ignore_ascii_case("exit") || command.eq_ignore_ascii_case("quit") {
+            break;
+        }
+
+        match robot.command(command) {
+            Ok(reply) => {
+                for line in format_reply(command, &reply) {
+                    println!("{}", line);
+                }
+            }
+            Err(err) => eprintln!("{:?}", err),
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            log::error!("Could not save history: {:?}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHARGER: &str = "Label,Value\r
+FuelPercent,80\r
+BatteryOverTemp,0\r
+ChargingActive,1\r
+ChargingEnabled,1\r
+ConfidentOnFuel,0\r
+OnReservedFuel,0\r
+EmptyFuel,0\r
+BatteryFailure,0\r
+ExtPwrPresent,1\r
+ThermistorPresent,1\r
+BattTempCAvg,31\r
+VBattV,16.25\r
+VExtV,22.62\r
+Charger_mAH,0\r
+Discharge_mAH,208\r
+";
+
+    #[test]
+    fn command_list_is_sorted_without_duplicates() {
+        let names: Vec<&str> = COMMANDS.iter().map(|(command, _)| *command).collect();
+        let mut sorted = names.clone();
+        sorted.sort_by_key(|name| name.to_lowercase());
+        sorted.dedup();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn completes_commands() {
+        let (start, matches) = completions("");
+        assert_eq!(start, 0);
+        assert_eq!(matches.len(), COMMANDS.len());
+
+        let (start, matches) = completions("getm");
+        assert_eq!(start, 0);
+        assert_eq!(matches, vec!["GetMotors"]);
+    }
+
+    #[test]
+    fn completes_arguments_of_any_case() {
+        let (start, matches) = completions("setled buttonG");
+        assert_eq!(start, 7);
+        assert_eq!(matches, vec!["ButtonGreen", "ButtonGreenDim"]);
+
+        let (_, matches) = completions("TestMode ");
+        assert_eq!(matches, vec!["On", "Off"]);
+    }
+
+    #[test]
+    fn completes_nothing_for_unknown_commands() {
+        assert!(completions("frobnicate o").1.is_empty());
+        assert!(completions("GetVersion ").1.is_empty());
+    }
+
+    #[test]
+    fn formats_known_replies_as_a_table() {
+        let lines = format_reply("GetCharger", CHARGER);
+        assert_eq!(lines.len(), 15);
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("fuel_percent ") && line.ends_with(" 80")));
+    }
+
+    #[test]
+    fn passes_other_replies_through() {
+        let reply = "Help Strlen = 1\r\nClean - Starts a cleaning\r\n";
+        assert_eq!(format_reply("Help", reply), vec![reply.trim_end()]);
+
+        // Arguments change the reply, so it is not parsed
+        let motors = format_reply("GetMotors Brush", CHARGER);
+        assert_eq!(motors, vec![CHARGER.trim_end()]);
+
+        // Nor is a reply that does not parse
+        let garbage = "Label,Value\r\nFuelPercent,lots\r\n";
+        assert_eq!(
+            format_reply("GetCharger", garbage),
+            vec![garbage.trim_end()]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use chrono::{Duration, Local};
+
+use crate::{NeatoRobot, RobotTime};
+
+const SECONDS_PER_WEEK: i64 = 7 * 86400;
+
+/// How far `robot` runs ahead of `host`, between minus and plus half a week,
+/// since the robot clock does not know which week it is.
+pub fn drift(robot: &RobotTime, host: &RobotTime) -> Duration {
+    let difference = robot.seconds_of_week() as i64 - host.seconds_of_week() as i64;
+    let wrapped =
+        (difference + SECONDS_PER_WEEK / 2).rem_euclid(SECONDS_PER_WEEK) - SECONDS_PER_WEEK / 2;
+    Duration::seconds(wrapped)
+}
+
+/// How far the robot clock runs ahead of the host's local time.
+///
+/// The host time is taken halfway the `GetTime` round trip, and the robot clock
+/// has a resolution of a second, so expect an error of about a second.
+pub fn measure_drift<R: NeatoRobot + ?Sized>(robot: &mut R) -> Result<Duration> {
+    let start = Instant::now();
+    let before = Local::now();
+    let robot_time = robot.get_time()?;
+    let round_trip = Duration::from_std(start.elapsed())?;
+    let host_time = RobotTime::from_datetime(&(before + round_trip / 2));
+    Ok(drift(&robot_time, &host_time))
+}
+
+/// Set the robot clock to the host's local time, returning the drift it had.
+pub fn sync_clock<R: NeatoRobot + ?Sized>(robot: &mut R) -> Result<Duration> {
+    let drift = measure_drift(robot)?;
+    robot.set_time(&RobotTime::from_datetime(&Local::now()))?;
+    log::info!("Synced robot clock, it was {} s off", drift.num_seconds());
+    Ok(drift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries};
+    use chrono::{NaiveTime, Timelike, Weekday};
+
+    fn time(weekday: Weekday, hour: u32, min: u32, sec: u32) -> RobotTime {
+        RobotTime::new(weekday, NaiveTime::from_hms_opt(hour, min, sec).unwrap())
+    }
+
+    #[test]
+    fn parses_gettime_and_formats_settime() {
+        let parsed: RobotTime = "Sunday 13:57:09".parse().unwrap();
+        assert_eq!(parsed, time(Weekday::Sun, 13, 57, 9));
+        assert_eq!(parsed.to_string(), "day 0 hour 13 min 57 sec 9");
+
+        let parsed: RobotTime = "Saturday 00:00:00".parse().unwrap();
+        assert_eq!(parsed.to_string(), "day 6 hour 0 min 0 sec 0");
+
+        assert!("Someday 13:57:09".parse::<RobotTime>().is_err());
+        assert!("Sunday 25:00:00".parse::<RobotTime>().is_err());
+    }
+
+    #[test]
+    fn drift_wraps_around_the_week() {
+        let sunday = time(Weekday::Sun, 0, 0, 10);
+        let saturday = time(Weekday::Sat, 23, 59, 50);
+        assert_eq!(drift(&saturday, &sunday), Duration::seconds(-20));
+        assert_eq!(drift(&sunday, &saturday), Duration::seconds(20));
+        assert_eq!(
+            drift(&time(Weekday::Mon, 12, 1, 0), &time(Weekday::Mon, 12, 0, 0)),
+            Duration::seconds(60)
+        );
+    }
+
+    #[test]
+    fn sync_clock_measures_drift_and_sets_host_time() {
+        // Start early in a second, so the robot is set to the second it is read in
+        while Local::now().nanosecond() >= 500_000_000 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let now = Local::now();
+        let ahead = now + Duration::seconds(90);
+        let settime = format!("settime {}", RobotTime::from_datetime(&now));
+        let port = ScriptedPort::new()
+            .expect(
+                "gettime",
+                &format!("gettime\r\n{}\r\n\x1a", ahead.format("%A %H:%M:%S")),
+            )
+            .expect(&settime, &format!("{}\r\n\x1a", settime));
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        let drift = sync_clock(&mut robot).unwrap();
+        assert!((89..=90).contains(&drift.num_seconds()), "{}", drift);
+        assert!(port.is_finished());
+    }
+}
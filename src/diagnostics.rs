@@ -0,0 +1,417 @@
+use anyhow::Result;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{LdsScan, NeatoRobot, Toggle};
+
+/// Readings per revolution, one per degree.
+const ANGLES: usize = 360;
+
+/// Thresholds for the LDS self-test.
+#[derive(Debug, Clone, Copy)]
+pub struct LdsTestConfig {
+    /// Number of scans to take.
+    pub scans: usize,
+    /// Lowest acceptable mean rotation speed, in Hz.
+    pub min_speed: f32,
+    /// Highest acceptable mean rotation speed, in Hz.
+    pub max_speed: f32,
+    /// Highest acceptable standard deviation of the rotation speed, in Hz.
+    pub max_speed_jitter: f32,
+    /// Highest acceptable fraction of invalid readings over all angles.
+    pub max_invalid_fraction: f32,
+    /// Width of the sectors checked for stuck readings, in degrees.
+    pub sector_width: usize,
+    /// Valid readings an angle needs before its distances count as stuck or not.
+    pub min_stuck_readings: usize,
+    /// Fraction of the angles of a sector that need enough valid readings before
+    /// the sector counts as stuck or not.
+    pub min_stuck_coverage: f32,
+    /// Lowest acceptable mean intensity of the valid readings.
+    pub min_mean_intensity: f32,
+}
+
+impl Default for LdsTestConfig {
+    fn default() -> Self {
+        Self {
+            scans: 20,
+            min_speed: 4.5,
+            max_speed: 5.5,
+            max_speed_jitter: 0.2,
+            max_invalid_fraction: 0.5,
+            sector_width: 10,
+            min_stuck_readings: 5,
+            min_stuck_coverage: 0.5,
+            min_mean_intensity: 50.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Statistics {
+    pub mean: f32,
+    pub std_dev: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Statistics {
+    /// `None` when there are no values.
+    pub fn of(values: &[f32]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let count = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / count;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f32>()
+            / count;
+        Some(Self {
+            mean,
+            std_dev: variance.sqrt(),
+            min: values.iter().cloned().fold(f32::INFINITY, f32::min),
+            max: values.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+        })
+    }
+}
+
+/// A range of angles, in degrees, `start` inclusive and `end` exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Sector {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Serialize)]
+pub enum LdsFailure {
+    #[error("No scans were taken")]
+    NoScans,
+    #[error("The LDS never reported its rotation speed")]
+    NoRotationSpeed,
+    #[error("Mean rotation speed of {mean} Hz is outside {min} to {max} Hz")]
+    SpeedOutOfRange { mean: f32, min: f32, max: f32 },
+    #[error("Rotation speed jitters by {jitter} Hz, more than {max} Hz")]
+    SpeedJitter { jitter: f32, max: f32 },
+    #[error("{fraction} of all readings are invalid, more than {max}")]
+    TooManyInvalid { fraction: f32, max: f32 },
+    #[error("Readings from {} to {} degrees never change", .0.start, .0.end)]
+    StuckSector(Sector),
+    #[error("Mean intensity of {mean} is below {min}")]
+    LowIntensity { mean: f32, min: f32 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LdsReport {
+    pub scans: usize,
+    /// `None` when no scan reported its speed.
+    pub rotation_speed: Option<Statistics>,
+    /// Fraction of invalid readings at each whole degree.
+    pub invalid_fraction: Vec<f32>,
+    /// Fraction of invalid readings over all angles.
+    pub total_invalid_fraction: f32,
+    /// Sectors whose valid distances are identical in every scan.
+    pub stuck_sectors: Vec<Sector>,
+    /// `None` when there were no valid readings.
+    pub intensity: Option<Statistics>,
+    /// Why the LDS failed, empty when it passed.
+    pub failures: Vec<LdsFailure>,
+}
+
+impl LdsReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Compute the health statistics of a series of scans and judge them against `config`.
+pub fn analyze(scans: &[LdsScan], config: &LdsTestConfig) -> LdsReport {
+    let speeds: Vec<f32> = scans
+        .iter()
+        .filter_map(|scan| scan.rotation_speed)
+        .collect();
+    let rotation_speed = Statistics::of(&speeds);
+
+    let mut readings = vec![0_usize; ANGLES];
+    let mut invalid = vec![0_usize; ANGLES];
+    let mut distances: Vec<Vec<u32>> = vec![vec![]; ANGLES];
+    let mut intensities = vec![];
+    for scan in scans {
+        for reading in &scan.readings {
+            let angle = reading.angle as usize % ANGLES;
+            readings[angle] += 1;
+            if reading.is_valid() {
+                distances[angle].push(reading.distance_mm);
+                intensities.push(reading.intensity as f32);
+            } else {
+                invalid[angle] += 1;
+            }
+        }
+    }
+
+    let invalid_fraction = readings
+        .iter()
+        .zip(&invalid)
+        .map(|(&readings, &invalid)| match readings {
+            0 => 1.0,
+            readings => invalid as f32 / readings as f32,
+        })
+        .collect();
+    let total_readings: usize = readings.iter().sum();
+    let total_invalid_fraction = match total_readings {
+        0 => 1.0,
+        total => invalid.iter().sum::<usize>() as f32 / total as f32,
+    };
+
+    // A live sensor always has some noise, so identical distances over many
+    // scans mean the readings are not being updated. Angles with only a few valid
+    // readings, such as those seeing nothing, can be identical by chance.
+    let sector_width = config.sector_width.clamp(1, ANGLES);
+    let min_readings = config.min_stuck_readings.max(2);
+    let stuck_sectors: Vec<Sector> = (0..ANGLES)
+        .step_by(sector_width)
+        .map(|start| Sector {
+            start,
+            end: (start + sector_width).min(ANGLES),
+        })
+        .filter(|sector| {
+            let judged: Vec<&Vec<u32>> = distances[sector.start..sector.end]
+                .iter()
+                .filter(|distances| distances.len() >= min_readings)
+                .collect();
+            let min_angles = ((sector.end - sector.start) as f32 * config.min_stuck_coverage)
+                .ceil()
+                .max(1.0) as usize;
+            judged.len() >= min_angles
+                && judged
+                    .iter()
+                    .all(|distances| distances.windows(2).all(|pair| pair[0] == pair[1]))
+        })
+        .collect();
+
+    let intensity = Statistics::of(&intensities);
+
+    let mut failures = vec![];
+    if scans.is_empty() {
+        failures.push(LdsFailure::NoScans);
+    } else {
+        match rotation_speed {
+            None => failures.push(LdsFailure::NoRotationSpeed),
+            Some(speed) => {
+                if speed.mean < config.min_speed || speed.mean > config.max_speed {
+                    failures.push(LdsFailure::SpeedOutOfRange {
+                        mean: speed.mean,
+                        min: config.min_speed,
+                        max: config.max_speed,
+                    });
+                }
+                if speed.std_dev > config.max_speed_jitter {
+                    failures.push(LdsFailure::SpeedJitter {
+                        jitter: speed.std_dev,
+                        max: config.max_speed_jitter,
+                    });
+                }
+            }
+        }
+        if total_invalid_fraction > config.max_invalid_fraction {
+            failures.push(LdsFailure::TooManyInvalid {
+                fraction: total_invalid_fraction,
+                max: config.max_invalid_fraction,
+            });
+        }
+        failures.extend(stuck_sectors.iter().copied().map(LdsFailure::StuckSector));
+        let mean_intensity = intensity.map_or(0.0, |intensity| intensity.mean);
+        if mean_intensity < config.min_mean_intensity {
+            failures.push(LdsFailure::LowIntensity {
+                mean: mean_intensity,
+                min: config.min_mean_intensity,
+            });
+        }
+    }
+
+    LdsReport {
+        scans: scans.len(),
+        rotation_speed,
+        invalid_fraction,
+        total_invalid_fraction,
+        stuck_sectors,
+        intensity,
+        failures,
+    }
+}
+
+/// Spin up the LDS in test mode, take `config.scans` scans and analyze them.
+///
+/// Leaves test mode and stops the LDS afterwards, also when a scan fails.
+pub fn test_lds<R: NeatoRobot + ?Sized>(
+    robot: &mut R,
+    config: &LdsTestConfig,
+) -> Result<LdsReport> {
+    let scans = take_scans(robot, config.scans);
+    let exit = robot.exit();
+    let report = analyze(&scans?, config);
+    exit?;
+    Ok(report)
+}
+
+fn take_scans<R: NeatoRobot + ?Sized>(robot: &mut R, count: usize) -> Result<Vec<LdsScan>> {
+    robot.set_testmode(Toggle::On)?;
+    robot.set_ldsrotation(Toggle::On)?;
+    (0..count).map(|_| robot.get_lds_scan()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LdsReading;
+
+    /// A scan at `speed` Hz with the reading `reading(angle)` of
+    /// (distance, intensity, error code) at every degree.
+    fn scan(speed: f32, reading: impl Fn(u16) -> (u32, u32, u16)) -> LdsScan {
+        LdsScan {
+            readings: (0..ANGLES as u16)
+                .map(|angle| {
+                    let (distance_mm, intensity, error_code) = reading(angle);
+                    LdsReading {
+                        angle,
+                        distance_mm,
+                        intensity,
+                        error_code,
+                    }
+                })
+                .collect(),
+            rotation_speed: Some(speed),
+        }
+    }
+
+    /// Healthy scans, with distances that vary a little from scan to scan.
+    fn healthy(count: usize) -> Vec<LdsScan> {
+        (0..count)
+            .map(|index| {
+                scan(5.0, |angle| {
+                    (1000 + (angle as u32 + index as u32) % 7, 100, 0)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn healthy_lds_passes() {
+        let report = analyze(&healthy(20), &LdsTestConfig::default());
+        assert!(report.passed(), "{:?}", report.failures);
+        assert_eq!(report.scans, 20);
+        assert_eq!(report.rotation_speed.unwrap().mean, 5.0);
+        assert_eq!(report.total_invalid_fraction, 0.0);
+        assert!(report.stuck_sectors.is_empty());
+    }
+
+    #[test]
+    fn no_scans_fail() {
+        let report = analyze(&[], &LdsTestConfig::default());
+        assert_eq!(report.failures, vec![LdsFailure::NoScans]);
+    }
+
+    #[test]
+    fn detects_speed_out_of_range() {
+        let mut scans = healthy(20);
+        for scan in &mut scans {
+            scan.rotation_speed = Some(3.0);
+        }
+        let report = analyze(&scans, &LdsTestConfig::default());
+        assert_eq!(
+            report.failures,
+            vec![LdsFailure::SpeedOutOfRange {
+                mean: 3.0,
+                min: 4.5,
+                max: 5.5
+            }]
+        );
+    }
+
+    #[test]
+    fn detects_speed_jitter() {
+        let mut scans = healthy(20);
+        for (index, scan) in scans.iter_mut().enumerate() {
+            scan.rotation_speed = Some(if index % 2 == 0 { 4.6 } else { 5.4 });
+        }
+        let report = analyze(&scans, &LdsTestConfig::default());
+        match report.failures.as_slice() {
+            [LdsFailure::SpeedJitter { jitter, max }] => {
+                assert!((jitter - 0.4).abs() < 1e-3);
+                assert_eq!(*max, 0.2);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_too_many_invalid_readings() {
+        let scans: Vec<LdsScan> = (0..20)
+            .map(|index| {
+                scan(5.0, |angle| match angle % 10 < 6 {
+                    true => (0, 0, 0x8035),
+                    false => (1000 + (angle as u32 + index) % 7, 100, 0),
+                })
+            })
+            .collect();
+        let report = analyze(&scans, &LdsTestConfig::default());
+        assert_eq!(report.invalid_fraction[0], 1.0);
+        assert_eq!(report.invalid_fraction[9], 0.0);
+        match report.failures.as_slice() {
+            [LdsFailure::TooManyInvalid { fraction, max }] => {
+                assert!((fraction - 0.6).abs() < 1e-6);
+                assert_eq!(*max, 0.5);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_stuck_sector() {
+        let scans: Vec<LdsScan> = (0..20)
+            .map(|index| {
+                scan(5.0, |angle| match angle {
+                    20..=29 => (1500, 100, 0),
+                    _ => (1000 + (angle as u32 + index) % 7, 100, 0),
+                })
+            })
+            .collect();
+        let report = analyze(&scans, &LdsTestConfig::default());
+        let sector = Sector { start: 20, end: 30 };
+        assert_eq!(report.stuck_sectors, vec![sector]);
+        assert_eq!(report.failures, vec![LdsFailure::StuckSector(sector)]);
+    }
+
+    #[test]
+    fn does_not_judge_angles_with_few_readings() {
+        // Only two scans see anything from 20 to 29 degrees, at the same distance
+        let scans: Vec<LdsScan> = (0..20)
+            .map(|index| {
+                scan(5.0, |angle| match angle {
+                    20..=29 if index < 2 => (1500, 100, 0),
+                    20..=29 => (0, 0, 0x8035),
+                    _ => (1000 + (angle as u32 + index) % 7, 100, 0),
+                })
+            })
+            .collect();
+        let report = analyze(&scans, &LdsTestConfig::default());
+        assert!(report.stuck_sectors.is_empty());
+    }
+
+    #[test]
+    fn does_not_judge_sectors_with_few_angles() {
+        // Only 3 of the angles from 20 to 29 degrees see anything, at the same distance
+        let scans: Vec<LdsScan> = (0..20)
+            .map(|index| {
+                scan(5.0, |angle| match angle {
+                    20..=22 => (1500, 100, 0),
+                    23..=29 => (0, 0, 0x8035),
+                    _ => (1000 + (angle as u32 + index) % 7, 100, 0),
+                })
+            })
+            .collect();
+        let report = analyze(&scans, &LdsTestConfig::default());
+        assert!(report.stuck_sectors.is_empty());
+    }
+}
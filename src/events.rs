@@ -0,0 +1,208 @@
+use std::time::SystemTime;
+
+use crate::DigitalSensorStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigitalSensorEvent {
+    DustbinRemoved,
+    DustbinInserted,
+    WheelLifted(Side),
+    WheelLowered(Side),
+    DockJackInserted,
+    DockJackRemoved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimestampedEvent {
+    pub timestamp: SystemTime,
+    pub event: DigitalSensorEvent,
+}
+
+/// Turns successive `get_digital_sensors` results into state change events.
+///
+/// The first status only establishes the baseline, so it never produces events.
+#[derive(Debug, Default)]
+pub struct DigitalSensorMonitor {
+    previous: Option<DigitalSensorStatus>,
+}
+
+impl DigitalSensorMonitor {
+    pub fn new() -> Self {
+        Self { previous: None }
+    }
+
+    pub fn update(&mut self, status: DigitalSensorStatus) -> Vec<TimestampedEvent> {
+        self.update_at(status, SystemTime::now())
+    }
+
+    pub fn update_at(
+        &mut self,
+        status: DigitalSensorStatus,
+        timestamp: SystemTime,
+    ) -> Vec<TimestampedEvent> {
+        let events = match &self.previous {
+            Some(previous) => diff(previous, &status),
+            None => vec![],
+        };
+        self.previous = Some(status);
+
+        events
+            .into_iter()
+            .map(|event| {
+                log::debug!("{:?}", event);
+                TimestampedEvent { timestamp, event }
+            })
+            .collect()
+    }
+}
+
+fn diff(previous: &DigitalSensorStatus, current: &DigitalSensorStatus) -> Vec<DigitalSensorEvent> {
+    let mut events = vec![];
+
+    if previous.sensor_dustbin_is_in != current.sensor_dustbin_is_in {
+        events.push(if current.sensor_dustbin_is_in {
+            DigitalSensorEvent::DustbinInserted
+        } else {
+            DigitalSensorEvent::DustbinRemoved
+        });
+    }
+
+    // A wheel extends when its side of the robot is lifted off the floor
+    let wheels = [
+        (
+            Side::Left,
+            previous.sensor_left_wheel_extended,
+            current.sensor_left_wheel_extended,
+        ),
+        (
+            Side::Right,
+            previous.sensor_right_wheel_extended,
+            current.sensor_right_wheel_extended,
+        ),
+    ];
+    for (side, was_extended, is_extended) in wheels.iter() {
+        if was_extended != is_extended {
+            events.push(if *is_extended {
+                DigitalSensorEvent::WheelLifted(*side)
+            } else {
+                DigitalSensorEvent::WheelLowered(*side)
+            });
+        }
+    }
+
+    if previous.sensor_dc_jack_is_in != current.sensor_dc_jack_is_in {
+        events.push(if current.sensor_dc_jack_is_in {
+            DigitalSensorEvent::DockJackInserted
+        } else {
+            DigitalSensorEvent::DockJackRemoved
+        });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> DigitalSensorStatus {
+        DigitalSensorStatus {
+            sensor_dustbin_is_in: true,
+            ..Default::default()
+        }
+    }
+
+    /// Events from going from `before` to `after`, after `before` set the baseline.
+    fn events(before: DigitalSensorStatus, after: DigitalSensorStatus) -> Vec<DigitalSensorEvent> {
+        let mut monitor = DigitalSensorMonitor::new();
+        assert!(monitor.update(before).is_empty());
+        monitor
+            .update(after)
+            .into_iter()
+            .map(|event| event.event)
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_status_has_no_events() {
+        assert!(events(status(), status()).is_empty());
+    }
+
+    #[test]
+    fn dustbin_removed_and_inserted() {
+        let removed = DigitalSensorStatus {
+            sensor_dustbin_is_in: false,
+            ..status()
+        };
+        assert_eq!(
+            events(status(), removed.clone()),
+            vec![DigitalSensorEvent::DustbinRemoved]
+        );
+        assert_eq!(
+            events(removed, status()),
+            vec![DigitalSensorEvent::DustbinInserted]
+        );
+    }
+
+    #[test]
+    fn wheels_lifted_and_lowered() {
+        let left_lifted = DigitalSensorStatus {
+            sensor_left_wheel_extended: true,
+            ..status()
+        };
+        let right_lifted = DigitalSensorStatus {
+            sensor_right_wheel_extended: true,
+            ..status()
+        };
+        assert_eq!(
+            events(status(), left_lifted.clone()),
+            vec![DigitalSensorEvent::WheelLifted(Side::Left)]
+        );
+        assert_eq!(
+            events(left_lifted, status()),
+            vec![DigitalSensorEvent::WheelLowered(Side::Left)]
+        );
+        assert_eq!(
+            events(status(), right_lifted.clone()),
+            vec![DigitalSensorEvent::WheelLifted(Side::Right)]
+        );
+        assert_eq!(
+            events(right_lifted, status()),
+            vec![DigitalSensorEvent::WheelLowered(Side::Right)]
+        );
+    }
+
+    #[test]
+    fn dc_jack_inserted() {
+        let docked = DigitalSensorStatus {
+            sensor_dc_jack_is_in: true,
+            ..status()
+        };
+        assert_eq!(
+            events(status(), docked.clone()),
+            vec![DigitalSensorEvent::DockJackInserted]
+        );
+        assert_eq!(
+            events(docked, status()),
+            vec![DigitalSensorEvent::DockJackRemoved]
+        );
+    }
+
+    #[test]
+    fn transition_is_reported_once() {
+        let mut monitor = DigitalSensorMonitor::new();
+        let removed = DigitalSensorStatus {
+            sensor_dustbin_is_in: false,
+            ..status()
+        };
+        monitor.update(status());
+        assert_eq!(monitor.update(removed.clone()).len(), 1);
+        assert!(monitor.update(removed).is_empty());
+    }
+}
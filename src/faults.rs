@@ -0,0 +1,325 @@
+use std::{convert::Infallible, fmt::Display, str::FromStr};
+
+use serde::Serialize;
+
+/// The faults the firmware reports through `GetErr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErrorKind {
+    BrushStuck,
+    BrushOverloaded,
+    VacuumStuck,
+    BumperStuck,
+    LeftWheelStuck,
+    RightWheelStuck,
+    LeftDropStuck,
+    RightDropStuck,
+    PickedUp,
+    Stuck,
+    DustBinMissing,
+    DustBinFull,
+    LdsJammed,
+    LdsDisconnected,
+    LdsBadPackets,
+    BatteryOverTemp,
+    BatteryFailure,
+    UnableToReturnToBase,
+    HardwareFailure,
+    Other,
+}
+
+/// Words identifying each fault in the firmware's names, such as
+/// `UI_ERROR_BRUSH_STUCK`, and messages, such as `Brush stuck`.
+/// More specific entries come first.
+const KINDS: [(&str, ErrorKind); 19] = [
+    ("BRUSH_STUCK", ErrorKind::BrushStuck),
+    ("BRUSH_OVERLOAD", ErrorKind::BrushOverloaded),
+    ("VACUUM_STUCK", ErrorKind::VacuumStuck),
+    ("BUMPER_STUCK", ErrorKind::BumperStuck),
+    ("LWHEEL_STUCK", ErrorKind::LeftWheelStuck),
+    ("LEFT_WHEEL_STUCK", ErrorKind::LeftWheelStuck),
+    ("RWHEEL_STUCK", ErrorKind::RightWheelStuck),
+    ("RIGHT_WHEEL_STUCK", ErrorKind::RightWheelStuck),
+    ("LDROP_STUCK", ErrorKind::LeftDropStuck),
+    ("RDROP_STUCK", ErrorKind::RightDropStuck),
+    ("PICKED_UP", ErrorKind::PickedUp),
+    ("DUST_BIN_MISSING", ErrorKind::DustBinMissing),
+    ("DUST_BIN_FULL", ErrorKind::DustBinFull),
+    ("LDS_JAMMED", ErrorKind::LdsJammed),
+    ("LDS_DISCONNECTED", ErrorKind::LdsDisconnected),
+    ("LDS_BAD_PACKETS", ErrorKind::LdsBadPackets),
+    ("OVERTEMP", ErrorKind::BatteryOverTemp),
+    ("UNABLE_TO_RETURN", ErrorKind::UnableToReturnToBase),
+    ("HARDWARE_FAILURE", ErrorKind::HardwareFailure),
+];
+
+/// Error numbers as `GetErr` reports them before the name, such as
+/// `243 - UI_ERROR_BRUSH_STUCK`. Neato has not published these, and firmware
+/// versions may number faults differently, so they are only used for messages
+/// that `ErrorKind::from_message` does not recognize.
+const CODES: [(u16, ErrorKind); 17] = [
+    (227, ErrorKind::DustBinMissing),
+    (228, ErrorKind::DustBinFull),
+    (229, ErrorKind::PickedUp),
+    (230, ErrorKind::BumperStuck),
+    (231, ErrorKind::LeftWheelStuck),
+    (232, ErrorKind::RightWheelStuck),
+    (233, ErrorKind::LeftDropStuck),
+    (234, ErrorKind::RightDropStuck),
+    (235, ErrorKind::LdsJammed),
+    (236, ErrorKind::LdsDisconnected),
+    (237, ErrorKind::LdsBadPackets),
+    (238, ErrorKind::BatteryOverTemp),
+    (239, ErrorKind::BatteryFailure),
+    (240, ErrorKind::UnableToReturnToBase),
+    (241, ErrorKind::HardwareFailure),
+    (242, ErrorKind::VacuumStuck),
+    (243, ErrorKind::BrushStuck),
+];
+
+impl ErrorKind {
+    /// The fault with Neato's error number `code`, `None` for numbers not in the table.
+    pub fn from_code(code: u16) -> Option<Self> {
+        CODES
+            .iter()
+            .find(|(known, _)| *known == code)
+            .map(|(_, kind)| *kind)
+    }
+
+    /// Recognize a fault by the firmware's name or message for it.
+    pub fn from_message(message: &str) -> Self {
+        let normalized = message.trim().to_uppercase().replace(' ', "_");
+        if let Some((_words, kind)) = KINDS.iter().find(|(words, _)| normalized.contains(words)) {
+            return *kind;
+        }
+        if normalized.contains("BATTERY") && normalized.contains("FAIL") {
+            ErrorKind::BatteryFailure
+        } else if normalized.contains("STUCK") {
+            ErrorKind::Stuck
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            ErrorKind::BrushStuck => "Brush stuck",
+            ErrorKind::BrushOverloaded => "Brush overloaded",
+            ErrorKind::VacuumStuck => "Vacuum stuck",
+            ErrorKind::BumperStuck => "Bumper stuck",
+            ErrorKind::LeftWheelStuck => "Left wheel stuck",
+            ErrorKind::RightWheelStuck => "Right wheel stuck",
+            ErrorKind::LeftDropStuck => "Left drop sensor stuck",
+            ErrorKind::RightDropStuck => "Right drop sensor stuck",
+            ErrorKind::PickedUp => "Picked up",
+            ErrorKind::Stuck => "Stuck",
+            ErrorKind::DustBinMissing => "Dust bin missing",
+            ErrorKind::DustBinFull => "Dust bin full",
+            ErrorKind::LdsJammed => "LDS jammed",
+            ErrorKind::LdsDisconnected => "LDS disconnected",
+            ErrorKind::LdsBadPackets => "LDS sends bad packets",
+            ErrorKind::BatteryOverTemp => "Battery too hot",
+            ErrorKind::BatteryFailure => "Battery failure",
+            ErrorKind::UnableToReturnToBase => "Unable to return to base",
+            ErrorKind::HardwareFailure => "Hardware failure",
+            ErrorKind::Other => "Other error",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// The error the firmware is reporting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RobotError {
+    /// Neato's error number, when the firmware reported one.
+    pub code: Option<u16>,
+    pub kind: ErrorKind,
+    /// The firmware's own text for the error.
+    pub message: String,
+}
+
+impl RobotError {
+    /// Parse a `GetErr` reply, such as `243 - UI_ERROR_BRUSH_STUCK`, by its message
+    /// and otherwise by its number. An empty reply means there is no error.
+    pub fn parse(reply: &str) -> Option<Self> {
+        let line = reply
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())?;
+
+        let (code, message) = match line.split_once(' ') {
+            Some((code, message)) => match code.parse::<u16>() {
+                Ok(code) => (Some(code), message.trim_start_matches(&[' ', '-'][..])),
+                Err(_) => (None, line),
+            },
+            None => match line.parse::<u16>() {
+                Ok(code) => (Some(code), ""),
+                Err(_) => (None, line),
+            },
+        };
+
+        let kind = match ErrorKind::from_message(message) {
+            ErrorKind::Other => code
+                .and_then(ErrorKind::from_code)
+                .unwrap_or(ErrorKind::Other),
+            kind => kind,
+        };
+        Some(Self {
+            code,
+            kind,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl Display for RobotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} ({}: {})", self.kind, code, self.message),
+            None => write!(f, "{} ({})", self.kind, self.message),
+        }
+    }
+}
+
+/// The firmware's system log, as comma separated rows under a header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SysLog {
+    /// Empty when the log has no header row.
+    pub columns: Vec<String>,
+    pub entries: Vec<Vec<String>>,
+}
+
+impl SysLog {
+    /// The value of `column` in entry `index`.
+    pub fn get(&self, index: usize, column: &str) -> Option<&str> {
+        let column = self.columns.iter().position(|name| name == column)?;
+        self.entries.get(index)?.get(column).map(String::as_str)
+    }
+}
+
+impl FromStr for SysLog {
+    type Err = Infallible;
+
+    /// Parse a `GetSysLog` reply. Lines without commas, such as titles, are skipped,
+    /// and the first row without numbers is taken as the header.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut log = SysLog::default();
+        for line in s.lines().map(|line| line.trim()) {
+            if !line.contains(',') {
+                continue;
+            }
+            let fields: Vec<String> = line
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .collect();
+            let is_header = log.columns.is_empty()
+                && log.entries.is_empty()
+                && fields.iter().all(|field| field.parse::<f64>().is_err());
+            if is_header {
+                log.columns = fields;
+            } else {
+                log.entries.push(fields);
+            }
+        }
+        Ok(log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries, NeatoRobot};
+
+    #[test]
+    fn no_error_is_none() {
+        assert_eq!(RobotError::parse(""), None);
+        assert_eq!(RobotError::parse("\r\n"), None);
+    }
+
+    #[test]
+    fn parses_code_and_name() {
+        let error = RobotError::parse("243 - UI_ERROR_BRUSH_STUCK\r\n").unwrap();
+        assert_eq!(error.code, Some(243));
+        assert_eq!(error.kind, ErrorKind::BrushStuck);
+        assert_eq!(error.message, "UI_ERROR_BRUSH_STUCK");
+    }
+
+    #[test]
+    fn message_takes_precedence_over_code() {
+        let error = RobotError::parse("243 - UI_ERROR_LWHEEL_STUCK").unwrap();
+        assert_eq!(error.code, Some(243));
+        assert_eq!(error.kind, ErrorKind::LeftWheelStuck);
+    }
+
+    #[test]
+    fn unknown_message_falls_back_to_code() {
+        let error = RobotError::parse("227 - Please put back the dirt bin").unwrap();
+        assert_eq!(error.kind, ErrorKind::DustBinMissing);
+    }
+
+    #[test]
+    fn unknown_code_falls_back_to_message() {
+        let error = RobotError::parse("999 - UI_ERROR_RWHEEL_STUCK").unwrap();
+        assert_eq!(error.code, Some(999));
+        assert_eq!(error.kind, ErrorKind::RightWheelStuck);
+
+        let error = RobotError::parse("999 - UI_ERROR_SOMETHING_NEW").unwrap();
+        assert_eq!(error.kind, ErrorKind::Other);
+    }
+
+    #[test]
+    fn parses_message_without_code() {
+        let error = RobotError::parse("Dust Bin Full\r\n").unwrap();
+        assert_eq!(error.code, None);
+        assert_eq!(error.kind, ErrorKind::DustBinFull);
+        assert_eq!(error.message, "Dust Bin Full");
+        assert_eq!(error.to_string(), "Dust bin full (Dust Bin Full)");
+
+        let error = RobotError::parse("Robot is stuck").unwrap();
+        assert_eq!(error.kind, ErrorKind::Stuck);
+    }
+
+    #[test]
+    fn parses_syslog_rows_under_header() {
+        let reply = "System Log\r
+Time,Event,Data\r
+1023,Boot,0\r
+1450,Error,243\r
+";
+        let log: SysLog = reply.parse().unwrap();
+        assert_eq!(log.columns, vec!["Time", "Event", "Data"]);
+        assert_eq!(log.entries.len(), 2);
+        assert_eq!(log.get(1, "Event"), Some("Error"));
+        assert_eq!(log.get(1, "Data"), Some("243"));
+        assert_eq!(log.get(2, "Event"), None);
+        assert_eq!(log.get(0, "Missing"), None);
+    }
+
+    #[test]
+    fn parses_syslog_without_header() {
+        let log: SysLog = "1023,Boot,0\r\n".parse().unwrap();
+        assert!(log.columns.is_empty());
+        assert_eq!(log.entries, vec![vec!["1023", "Boot", "0"]]);
+
+        let empty: SysLog = "".parse().unwrap();
+        assert_eq!(empty, SysLog::default());
+    }
+
+    #[test]
+    fn reads_errors_from_robot() {
+        let port = ScriptedPort::new()
+            .expect("geterr", "geterr\r\n243 - UI_ERROR_BRUSH_STUCK\r\n\x1a")
+            .expect("geterr clear", "geterr clear\r\n\x1a")
+            .expect("geterr", "geterr\r\n\x1a");
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        assert_eq!(
+            robot.get_error().unwrap().unwrap().kind,
+            ErrorKind::BrushStuck
+        );
+        robot.clear_error().unwrap();
+        assert_eq!(robot.get_error().unwrap(), None);
+        assert!(port.is_finished());
+    }
+}
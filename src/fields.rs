@@ -0,0 +1,178 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use thiserror::Error;
+
+use crate::table::{Table, Value};
+
+/// How a status struct reads one field of a `Name,Value` or `Name,Unit,Value` reply.
+pub(crate) struct Field<T> {
+    pub name: &'static str,
+    /// Whether a reply without this field is an error rather than left at its default.
+    pub required: bool,
+    /// `None` when the value has the wrong type for the field.
+    pub set: fn(&mut T, &Value) -> Option<()>,
+}
+
+/// A `Field` that sets `$field` from the row named `$name`, optionally `required`.
+macro_rules! field {
+    ($name:literal, $field:ident $(, $required:ident)?) => {
+        $crate::fields::Field {
+            name: $name,
+            required: field!(@required $($required)?),
+            set: |status, value| {
+                status.$field = $crate::fields::FieldValue::from_value(value)?;
+                Some(())
+            },
+        }
+    };
+    (@required) => {
+        false
+    };
+    (@required required) => {
+        true
+    };
+}
+
+/// Types a `Field` can hold.
+pub(crate) trait FieldValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FieldValue for i32 {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FieldValue for f32 {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(value) => Some(*value as f32),
+            Value::Float(value) => Some(*value),
+            Value::Text(_) => None,
+        }
+    }
+}
+
+impl FieldValue for bool {
+    /// 1 is true and any other integer false, as in `GetDigitalSensors`.
+    fn from_value(value: &Value) -> Option<Self> {
+        i32::from_value(value).map(|value| value == 1)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParseReplyError {
+    #[error("Invalid value for {name}: {value:?}")]
+    InvalidValue { name: String, value: Value },
+    #[error("Reply lacks the fields {0:?}")]
+    MissingFields(Vec<&'static str>),
+}
+
+/// Parse a reply into a `T` with `fields`, also returning the rows no field knows,
+/// so a firmware update that adds fields does not go unnoticed.
+pub(crate) fn parse_fields<T: Default>(
+    reply: &str,
+    fields: &[Field<T>],
+) -> Result<(T, BTreeMap<String, Value>), ParseReplyError> {
+    let table = Table::from_str(reply).unwrap_or_else(|never| match never {});
+    let mut status = T::default();
+    let mut unknown = BTreeMap::new();
+    let mut missing: Vec<&'static str> = fields
+        .iter()
+        .filter(|field| field.required)
+        .map(|field| field.name)
+        .collect();
+
+    for row in table.rows {
+        match fields.iter().find(|field| field.name == row.name) {
+            Some(field) => {
+                if (field.set)(&mut status, &row.value).is_none() {
+                    return Err(ParseReplyError::InvalidValue {
+                        name: row.name,
+                        value: row.value,
+                    });
+                }
+                missing.retain(|name| *name != field.name);
+            }
+            None => {
+                log::debug!("Extra field {} = {:?}", row.name, row.value);
+                unknown.insert(row.name, row.value);
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(ParseReplyError::MissingFields(missing));
+    }
+    Ok((status, unknown))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, ChargerStatus, DSeries, MotorStatus, NeatoRobot};
+
+    const MOTORS: &str = "Parameter,Value\r
+Brush_RPM,0\r
+Brush_mA,0\r
+Vacuum_RPM,0\r
+Vacuum_mA,0\r
+LeftWheel_RPM,0\r
+LeftWheel_Load%,0\r
+LeftWheel_PositionInMM,12\r
+LeftWheel_Speed,0\r
+RightWheel_RPM,0\r
+RightWheel_Load%,0\r
+RightWheel_PositionInMM,-4\r
+RightWheel_Speed,0\r
+ROTATION_SPEED,0\r
+SideBrush_mA,0\r
+";
+
+    #[test]
+    fn collects_extra_fields() {
+        let status: MotorStatus = MOTORS.parse().unwrap();
+        assert_eq!(status.left_wheel_position_in_mm, 12);
+        assert_eq!(status.right_wheel_position_in_mm, -4);
+        assert_eq!(status.extra.len(), 1);
+        assert_eq!(status.extra["ROTATION_SPEED"], Value::Int(0));
+    }
+
+    #[test]
+    fn reports_missing_required_fields() {
+        match "Parameter,Value\nBrush_RPM,0\nVacuum_RPM,0\n".parse::<MotorStatus>() {
+            Err(ParseReplyError::MissingFields(names)) => assert_eq!(
+                names,
+                vec!["LeftWheel_PositionInMM", "RightWheel_PositionInMM"]
+            ),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_value_of_wrong_type() {
+        let reply = "Label,Value\nFuelPercent,high\n";
+        assert!(matches!(
+            reply.parse::<ChargerStatus>(),
+            Err(ParseReplyError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_until_end_of_reply() {
+        let port = ScriptedPort::new()
+            .expect("getmotors", &format!("getmotors\r\n{}\x1a", MOTORS))
+            .expect("getmotors", &format!("getmotors\r\n{}\x1a", MOTORS));
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        for _ in 0..2 {
+            let status = robot.get_motors().unwrap();
+            assert_eq!(status.extra.len(), 1);
+        }
+        assert!(port.is_finished());
+    }
+}
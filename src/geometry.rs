@@ -0,0 +1,134 @@
+use std::f32::consts::PI;
+
+/// A point in the plane, in meters.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Point2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point2 {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn distance(&self, other: &Point2) -> f32 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+/// A planar pose: position in meters and heading in radians, counter-clockwise from the x axis.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Pose2D {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+}
+
+impl Pose2D {
+    pub fn new(x: f32, y: f32, theta: f32) -> Self {
+        Self {
+            x,
+            y,
+            theta: normalize_angle(theta),
+        }
+    }
+
+    /// Express a point given in this pose's frame in the parent frame.
+    pub fn transform(&self, point: &Point2) -> Point2 {
+        let (sin, cos) = self.theta.sin_cos();
+        Point2 {
+            x: self.x + cos * point.x - sin * point.y,
+            y: self.y + sin * point.x + cos * point.y,
+        }
+    }
+
+    /// Chain `other`, which is relative to this pose, onto this pose.
+    pub fn compose(&self, other: &Pose2D) -> Pose2D {
+        let position = self.transform(&Point2::new(other.x, other.y));
+        Pose2D::new(position.x, position.y, self.theta + other.theta)
+    }
+
+    pub fn inverse(&self) -> Pose2D {
+        let (sin, cos) = self.theta.sin_cos();
+        Pose2D::new(
+            -cos * self.x - sin * self.y,
+            sin * self.x - cos * self.y,
+            -self.theta,
+        )
+    }
+
+    /// The pose of `other` as seen from this pose.
+    pub fn between(&self, other: &Pose2D) -> Pose2D {
+        self.inverse().compose(other)
+    }
+
+    /// Linear interpolation, taking the short way around for the heading.
+    pub fn interpolate(&self, other: &Pose2D, fraction: f32) -> Pose2D {
+        Pose2D::new(
+            self.x + (other.x - self.x) * fraction,
+            self.y + (other.y - self.y) * fraction,
+            self.theta + normalize_angle(other.theta - self.theta) * fraction,
+        )
+    }
+}
+
+/// Wrap an angle into [-pi, pi).
+pub fn normalize_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped.is_finite() {
+        wrapped
+    } else {
+        angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_pose_near(pose: &Pose2D, expected: &Pose2D) {
+        assert!(
+            (pose.x - expected.x).abs() < EPSILON
+                && (pose.y - expected.y).abs() < EPSILON
+                && normalize_angle(pose.theta - expected.theta).abs() < EPSILON,
+            "{:?} is not {:?}",
+            pose,
+            expected
+        );
+    }
+
+    #[test]
+    fn identity_composes_to_the_same_pose() {
+        let pose = Pose2D::new(1.5, -0.5, 2.0);
+        assert_pose_near(&pose.compose(&Pose2D::default()), &pose);
+        assert_pose_near(&Pose2D::default().compose(&pose), &pose);
+    }
+
+    #[test]
+    fn compose_moves_in_the_pose_frame() {
+        let pose = Pose2D::new(1.0, 2.0, FRAC_PI_2);
+        let forward = Pose2D::new(1.0, 0.0, FRAC_PI_2);
+        assert_pose_near(&pose.compose(&forward), &Pose2D::new(1.0, 3.0, PI));
+    }
+
+    #[test]
+    fn inverse_undoes_compose() {
+        let pose = Pose2D::new(0.3, -1.2, 2.5);
+        let other = Pose2D::new(-0.7, 0.4, -1.0);
+        assert_pose_near(&pose.compose(&pose.inverse()), &Pose2D::default());
+        assert_pose_near(&pose.inverse().compose(&pose), &Pose2D::default());
+        assert_pose_near(&pose.inverse().inverse(), &pose);
+        assert_pose_near(&pose.compose(&pose.between(&other)), &other);
+    }
+
+    #[test]
+    fn normalizes_angles_into_half_open_range() {
+        assert!((normalize_angle(3.0 * PI) + PI).abs() < EPSILON);
+        assert!((normalize_angle(-FRAC_PI_2 - 2.0 * PI) + FRAC_PI_2).abs() < EPSILON);
+        assert_eq!(normalize_angle(0.5), 0.5);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::NeatoRobot;
+
+/// Stop the motors, turn off the LDS and leave test mode, trying every step even
+/// when an earlier one fails.
+pub fn restore<R: NeatoRobot + ?Sized>(robot: &mut R) -> Result<()> {
+    let motors = robot.set_motors(0, 0, 0);
+    let exit = robot.exit();
+    motors.and(exit)
+}
+
+/// Owns a robot and restores it with `restore` when dropped, also when unwinding
+/// from a panic.
+///
+/// Dereferences to the robot, so it can be used wherever the robot is.
+pub struct RobotGuard<R: NeatoRobot> {
+    robot: Option<R>,
+}
+
+impl<R: NeatoRobot> RobotGuard<R> {
+    pub fn new(robot: R) -> Self {
+        Self { robot: Some(robot) }
+    }
+
+    /// Restore the robot now and hand it back, reporting whether that worked.
+    pub fn release(mut self) -> Result<R> {
+        let mut robot = self.robot.take().expect("robot is only taken once");
+        restore(&mut robot)?;
+        Ok(robot)
+    }
+
+    /// Hand back the robot as it is, without restoring it.
+    pub fn disarm(mut self) -> R {
+        self.robot.take().expect("robot is only taken once")
+    }
+}
+
+impl<R: NeatoRobot> Deref for RobotGuard<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.robot.as_ref().expect("robot is only taken once")
+    }
+}
+
+impl<R: NeatoRobot> DerefMut for RobotGuard<R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.robot.as_mut().expect("robot is only taken once")
+    }
+}
+
+impl<R: NeatoRobot> Drop for RobotGuard<R> {
+    fn drop(&mut self) {
+        if let Some(robot) = &mut self.robot {
+            log::info!("Restoring robot state");
+            if let Err(err) = restore(robot) {
+                log::error!("Could not restore robot state: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Install a handler for Ctrl-C, SIGTERM and SIGHUP that clears the returned flag.
+///
+/// Loops should check the flag and return, so their `RobotGuard` gets dropped.
+/// A second signal exits immediately, for when the robot no longer answers.
+/// Can only be called once per process.
+pub fn shutdown_flag() -> Result<Arc<AtomicBool>> {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || {
+        if handler_running.swap(false, Ordering::SeqCst) {
+            log::info!("Shutting down, signal again to quit immediately");
+        } else {
+            std::process::exit(130);
+        }
+    })
+    .map_err(|err| anyhow!("Could not install signal handler: {}", err))?;
+    Ok(running)
+}
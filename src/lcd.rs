@@ -0,0 +1,168 @@
+use anyhow::Result;
+
+use crate::{LcdColor, LcdCommand, NeatoRobot};
+
+/// Size of the LCD in pixels.
+pub const LCD_WIDTH: u16 = 128;
+pub const LCD_HEIGHT: u16 = 64;
+
+/// Columns between the lines of the battery gauge.
+const GAUGE_SPACING: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RobotMode {
+    Idle,
+    Teleoperated,
+    Autonomous,
+}
+
+/// Status to show on the LCD with `show_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusScreen {
+    pub battery_percent: u8,
+    pub mode: RobotMode,
+}
+
+impl StatusScreen {
+    /// The `SetLCD` operations that draw this screen.
+    ///
+    /// `SetLCD` can only draw lines across the whole screen, so the status is shown
+    /// in shapes readable from across a room: the battery charge as vertical lines
+    /// filling the screen from the left, a frame when teleoperated and inverted
+    /// colours when autonomous. `SetLCD` has no operation to set a single pixel or
+    /// draw text, so there is no way to show a hostname or IP address.
+    pub fn commands(&self) -> Vec<LcdCommand> {
+        let (background, foreground) = match self.mode {
+            RobotMode::Autonomous => (LcdColor::Black, LcdColor::White),
+            _ => (LcdColor::White, LcdColor::Black),
+        };
+        let mut commands = vec![
+            LcdCommand::Background(background),
+            LcdCommand::Foreground(foreground),
+        ];
+
+        if self.mode == RobotMode::Teleoperated {
+            commands.extend(vec![
+                LcdCommand::HorizontalLine(0),
+                LcdCommand::HorizontalLine(LCD_HEIGHT - 1),
+                LcdCommand::VerticalLine(0),
+                LcdCommand::VerticalLine(LCD_WIDTH - 1),
+            ]);
+        }
+
+        let filled = LCD_WIDTH as u32 * self.battery_percent.min(100) as u32 / 100;
+        commands.extend(
+            (GAUGE_SPACING..filled as u16)
+                .step_by(GAUGE_SPACING as usize)
+                .map(LcdCommand::VerticalLine),
+        );
+        commands
+    }
+}
+
+/// Draw `screen` on the LCD. Needs test mode.
+pub fn show_status<R: NeatoRobot + ?Sized>(robot: &mut R, screen: &StatusScreen) -> Result<()> {
+    for command in screen.commands() {
+        robot.set_lcd(command)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries};
+
+    fn arguments(screen: &StatusScreen) -> Vec<String> {
+        screen
+            .commands()
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn formats_setlcd_arguments() {
+        let commands = [
+            (LcdCommand::Background(LcdColor::White), "bgwhite"),
+            (LcdCommand::Background(LcdColor::Black), "bgblack"),
+            (LcdCommand::Foreground(LcdColor::White), "fgwhite"),
+            (LcdCommand::Foreground(LcdColor::Black), "fgblack"),
+            (LcdCommand::HorizontalLine(10), "hline 10"),
+            (LcdCommand::VerticalLine(127), "vline 127"),
+            (LcdCommand::HorizontalBars, "hbars"),
+            (LcdCommand::VerticalBars, "vbars"),
+            (LcdCommand::Contrast(40), "contrast 40"),
+        ];
+        for (command, argument) in commands.iter() {
+            assert_eq!(command.to_string(), *argument);
+            assert_eq!(argument.parse::<LcdCommand>().unwrap(), *command);
+        }
+        assert_eq!(
+            "HLine 3".parse::<LcdCommand>().unwrap(),
+            LcdCommand::HorizontalLine(3)
+        );
+        assert!("contrast 64".parse::<LcdCommand>().is_err());
+        assert!("pixel 1 2".parse::<LcdCommand>().is_err());
+    }
+
+    #[test]
+    fn idle_screen_shows_battery_gauge() {
+        let screen = StatusScreen {
+            battery_percent: 10,
+            mode: RobotMode::Idle,
+        };
+        // 10% of 128 columns is 12, filled every other column from column 2
+        assert_eq!(
+            arguments(&screen),
+            vec!["bgwhite", "fgblack", "vline 2", "vline 4", "vline 6", "vline 8", "vline 10"]
+        );
+    }
+
+    #[test]
+    fn teleoperated_screen_has_frame() {
+        let screen = StatusScreen {
+            battery_percent: 0,
+            mode: RobotMode::Teleoperated,
+        };
+        assert_eq!(
+            arguments(&screen),
+            vec![
+                "bgwhite",
+                "fgblack",
+                "hline 0",
+                "hline 63",
+                "vline 0",
+                "vline 127"
+            ]
+        );
+    }
+
+    #[test]
+    fn autonomous_screen_is_inverted() {
+        let screen = StatusScreen {
+            battery_percent: 100,
+            mode: RobotMode::Autonomous,
+        };
+        let arguments = arguments(&screen);
+        assert_eq!(arguments[..2], ["bgblack", "fgwhite"]);
+        assert_eq!(arguments.last().unwrap(), "vline 126");
+        assert_eq!(arguments.len(), 2 + 63);
+    }
+
+    #[test]
+    fn sends_setlcd_commands() {
+        let port = ScriptedPort::new()
+            .expect("setlcd bgwhite", "setlcd bgwhite\r\n\x1a")
+            .expect("setlcd fgblack", "setlcd fgblack\r\n\x1a")
+            .expect("setlcd vline 2", "setlcd vline 2\r\n\x1a");
+        let mut robot = DSeries::new(Box::new(port.clone()));
+        let screen = StatusScreen {
+            battery_percent: 3,
+            mode: RobotMode::Idle,
+        };
+
+        show_status(&mut robot, &screen).unwrap();
+        assert!(port.is_finished());
+    }
+}
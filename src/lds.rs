@@ -0,0 +1,234 @@
+use crate::geometry::{Point2, Pose2D};
+
+/// Direction in which the angle index of `get_scan_ranges` increases, seen from above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationDirection {
+    CounterClockwise,
+    Clockwise,
+}
+
+/// Pose of the laser distance sensor on the robot base.
+///
+/// `x` and `y` are in meters from the base center, forward and to the left.
+/// `yaw` is the heading of angle index 0 in radians, counter-clockwise from straight ahead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LdsMount {
+    pub x: f32,
+    pub y: f32,
+    pub yaw: f32,
+    pub direction: RotationDirection,
+}
+
+impl Default for LdsMount {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            yaw: 0.0,
+            direction: RotationDirection::CounterClockwise,
+        }
+    }
+}
+
+impl LdsMount {
+    fn pose(&self) -> Pose2D {
+        Pose2D::new(self.x, self.y, self.yaw)
+    }
+}
+
+/// Converts LDS scans into Cartesian points in the robot base frame.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanProjector {
+    pub mount: LdsMount,
+    /// Readings shorter than this, in meters, are dropped. The LDS reports 0 for invalid readings.
+    pub min_range: f32,
+    /// Readings longer than this, in meters, are dropped.
+    pub max_range: f32,
+}
+
+impl Default for ScanProjector {
+    fn default() -> Self {
+        Self::new(LdsMount::default())
+    }
+}
+
+impl ScanProjector {
+    pub fn new(mount: LdsMount) -> Self {
+        Self {
+            mount,
+            min_range: 0.02,
+            max_range: 5.0,
+        }
+    }
+
+    /// Angle of the beam at `index` in the LDS frame, in radians counter-clockwise.
+    /// The index of a reading is its angle in degrees.
+    pub fn beam_angle(&self, index: usize) -> f32 {
+        let angle = (index as f32).to_radians();
+        match self.mount.direction {
+            RotationDirection::CounterClockwise => angle,
+            RotationDirection::Clockwise => -angle,
+        }
+    }
+
+    pub fn is_valid(&self, range: f32) -> bool {
+        range.is_finite() && range >= self.min_range && range <= self.max_range
+    }
+
+    /// Project the valid readings of `ranges`, as returned by `get_scan_ranges`.
+    pub fn project(&self, ranges: &[f32]) -> Vec<Point2> {
+        let lds = self.mount.pose();
+        self.beams(ranges)
+            .map(|(_index, beam)| lds.transform(&beam))
+            .collect()
+    }
+
+    /// Project `ranges` while undoing the motion of the robot during the scan.
+    ///
+    /// `start` and `end` are the odometry poses at the first and last reading.
+    /// Readings are assumed to be evenly spaced in time over the revolution.
+    /// The points are expressed in the base frame at `end`.
+    pub fn project_deskewed(&self, ranges: &[f32], start: &Pose2D, end: &Pose2D) -> Vec<Point2> {
+        let lds = self.mount.pose();
+        let to_end = end.inverse();
+        let last = ranges.len().saturating_sub(1).max(1) as f32;
+        self.beams(ranges)
+            .map(|(index, beam)| {
+                let base = start.interpolate(end, index as f32 / last);
+                to_end.transform(&base.transform(&lds.transform(&beam)))
+            })
+            .collect()
+    }
+
+    fn beams<'a>(&'a self, ranges: &'a [f32]) -> impl Iterator<Item = (usize, Point2)> + 'a {
+        ranges
+            .iter()
+            .enumerate()
+            .filter(move |(_index, range)| self.is_valid(**range))
+            .map(move |(index, range)| {
+                let (sin, cos) = self.beam_angle(index).sin_cos();
+                (index, Point2::new(range * cos, range * sin))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_near(point: &Point2, x: f32, y: f32) {
+        assert!(
+            (point.x - x).abs() < EPSILON && (point.y - y).abs() < EPSILON,
+            "{:?} is not ({}, {})",
+            point,
+            x,
+            y
+        );
+    }
+
+    /// A scan of 360 invalid readings with `range` at `degrees`.
+    fn scan(degrees: usize, range: f32) -> Vec<f32> {
+        let mut ranges = vec![0.0; 360];
+        ranges[degrees] = range;
+        ranges
+    }
+
+    #[test]
+    fn zero_degrees_is_straight_ahead() {
+        let points = ScanProjector::default().project(&scan(0, 1.0));
+        assert_eq!(points.len(), 1);
+        assert_near(&points[0], 1.0, 0.0);
+    }
+
+    #[test]
+    fn counter_clockwise_90_degrees_is_left() {
+        let points = ScanProjector::default().project(&scan(90, 1.0));
+        assert_near(&points[0], 0.0, 1.0);
+    }
+
+    #[test]
+    fn clockwise_90_degrees_is_right() {
+        let projector = ScanProjector::new(LdsMount {
+            direction: RotationDirection::Clockwise,
+            ..Default::default()
+        });
+        assert_near(&projector.project(&scan(90, 1.0))[0], 0.0, -1.0);
+    }
+
+    #[test]
+    fn index_is_degrees_for_short_scans() {
+        let mut ranges = vec![0.0; 91];
+        ranges[90] = 1.0;
+        assert_near(&ScanProjector::default().project(&ranges)[0], 0.0, 1.0);
+    }
+
+    #[test]
+    fn applies_mount_offset_and_yaw() {
+        let projector = ScanProjector::new(LdsMount {
+            x: -0.1,
+            y: 0.05,
+            yaw: FRAC_PI_2,
+            ..Default::default()
+        });
+        // Straight ahead of a sensor turned to the left
+        assert_near(&projector.project(&scan(0, 1.0))[0], -0.1, 1.05);
+    }
+
+    #[test]
+    fn drops_invalid_and_out_of_range_readings() {
+        let mut ranges = vec![0.0; 360];
+        ranges[10] = 0.01;
+        ranges[20] = 6.0;
+        ranges[30] = f32::NAN;
+        ranges[40] = f32::INFINITY;
+        ranges[50] = 2.0;
+        let points = ScanProjector::default().project(&ranges);
+        assert_eq!(points.len(), 1);
+        let (sin, cos) = 50f32.to_radians().sin_cos();
+        assert_near(&points[0], 2.0 * cos, 2.0 * sin);
+    }
+
+    #[test]
+    fn deskew_without_motion_is_a_plain_projection() {
+        let mut ranges = vec![1.0; 360];
+        ranges[45] = 2.0;
+        let pose = Pose2D::new(1.0, -2.0, 0.5);
+        let projector = ScanProjector::default();
+        let deskewed = projector.project_deskewed(&ranges, &pose, &pose);
+        for (point, expected) in deskewed.iter().zip(projector.project(&ranges).iter()) {
+            assert_near(point, expected.x, expected.y);
+        }
+    }
+
+    #[test]
+    fn deskew_undoes_translation_during_the_scan() {
+        // Readings straight ahead at the start, to the left a quarter of the way
+        // through and behind at the end, while driving 1 m forward
+        let mut ranges = vec![0.0; 360];
+        ranges[0] = 1.0;
+        ranges[90] = 1.0;
+        ranges[359] = 1.0;
+        let start = Pose2D::default();
+        let end = Pose2D::new(1.0, 0.0, 0.0);
+        let points = ScanProjector::default().project_deskewed(&ranges, &start, &end);
+        assert_eq!(points.len(), 3);
+        assert_near(&points[0], 0.0, 0.0);
+        assert_near(&points[1], 90.0 / 359.0 - 1.0, 1.0);
+        let (sin, cos) = 359f32.to_radians().sin_cos();
+        assert_near(&points[2], cos, sin);
+    }
+
+    #[test]
+    fn deskew_undoes_rotation_and_translation_during_the_scan() {
+        // Turning a quarter to the left while driving 1 m forward
+        let ranges = scan(0, 2.0);
+        let start = Pose2D::default();
+        let end = Pose2D::new(1.0, 0.0, FRAC_PI_2);
+        let points = ScanProjector::default().project_deskewed(&ranges, &start, &end);
+        // The wall 2 m ahead of the start is 1 m to the right of the end pose
+        assert_near(&points[0], 0.0, -1.0);
+    }
+}
@@ -1,30 +1,402 @@
 use anyhow::Context;
-use std::{fmt::Display, io, num::ParseFloatError, num::ParseIntError, str::FromStr, thread, time};
+use calibration::{CalInfo, DistanceCalPoint};
+use chrono::{Datelike, NaiveTime, Timelike, Weekday};
+use faults::{RobotError, SysLog};
+use fields::{Field, ParseReplyError};
+use schedule::Schedule;
+use std::{
+    collections::BTreeMap, fmt::Display, io, num::ParseFloatError, num::ParseIntError,
+    str::FromStr, thread, time,
+};
+use table::{Table, Value};
+use usage::Warranty;
 
 use io::Write;
-use serialport::SerialPort;
+use serde::Serialize;
+use serialport::{ClearBuffer, SerialPort};
 
 use anyhow::Result;
 
 use thiserror::Error;
 
+pub mod bridge;
+pub mod calibration;
+pub mod clock;
+pub mod diagnostics;
+pub mod events;
+pub mod faults;
+#[macro_use]
+pub mod fields;
+pub mod geometry;
+pub mod guard;
+pub mod lcd;
+pub mod lds;
+#[cfg(feature = "mapping")]
+pub mod mapping;
+pub mod odometry;
+pub mod recording;
+pub mod scan_matching;
+pub mod schedule;
+#[cfg(any(test, feature = "testing"))]
+pub mod scripted_port;
+pub mod stream;
+pub mod table;
+pub mod telemetry;
+pub mod usage;
+
 #[derive(Debug)]
 pub enum Toggle {
     On,
     Off,
 }
 
-impl ToString for Toggle {
-    fn to_string(&self) -> String {
+impl Display for Toggle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Toggle::On => write!(f, "on"),
+            Toggle::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// The sounds in the firmware's library, numbered as `PlaySound SoundID` expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundId {
+    WakingUp = 0,
+    StartingCleaning = 1,
+    CleaningCompleted = 2,
+    AttentionNeeded = 3,
+    BackingUpIntoBase = 4,
+    DockingCompleted = 5,
+    TestSound1 = 6,
+    TestSound2 = 7,
+    TestSound3 = 8,
+    TestSound4 = 9,
+    TestSound5 = 10,
+    Exploring = 11,
+    ShutDown = 12,
+    PickedUp = 13,
+    GoingToSleep = 14,
+    ReturningHome = 15,
+    UserCanceledCleaning = 16,
+    UserTerminatedCleaning = 17,
+    SlippedOffBase = 18,
+    Alert = 19,
+    ThankYou = 20,
+    /// Stop the sound that is playing.
+    Stop,
+}
+
+impl SoundId {
+    pub const ALL: [SoundId; 22] = [
+        SoundId::WakingUp,
+        SoundId::StartingCleaning,
+        SoundId::CleaningCompleted,
+        SoundId::AttentionNeeded,
+        SoundId::BackingUpIntoBase,
+        SoundId::DockingCompleted,
+        SoundId::TestSound1,
+        SoundId::TestSound2,
+        SoundId::TestSound3,
+        SoundId::TestSound4,
+        SoundId::TestSound5,
+        SoundId::Exploring,
+        SoundId::ShutDown,
+        SoundId::PickedUp,
+        SoundId::GoingToSleep,
+        SoundId::ReturningHome,
+        SoundId::UserCanceledCleaning,
+        SoundId::UserTerminatedCleaning,
+        SoundId::SlippedOffBase,
+        SoundId::Alert,
+        SoundId::ThankYou,
+        SoundId::Stop,
+    ];
+}
+
+impl Display for SoundId {
+    /// The arguments of `PlaySound` for this sound.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SoundId::Stop => write!(f, "stop"),
+            sound => write!(f, "soundid {}", *sound as u8),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown sound {0:?}, expected a number from 0 to 20 or \"stop\"")]
+pub struct UnknownSoundError(String);
+
+impl FromStr for SoundId {
+    type Err = UnknownSoundError;
+
+    /// Parse a sound number or `stop`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("stop") {
+            return Ok(SoundId::Stop);
+        }
+        s.parse::<usize>()
+            .ok()
+            .filter(|&id| id < SoundId::Stop as usize)
+            .map(|id| SoundId::ALL[id])
+            .ok_or_else(|| UnknownSoundError(s.to_string()))
+    }
+}
+
+/// The LED states `SetLED` can select.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Led {
+    BacklightOn,
+    BacklightOff,
+    ButtonAmber,
+    ButtonGreen,
+    ButtonAmberDim,
+    ButtonGreenDim,
+    ButtonOff,
+    /// The red ring around the start button.
+    StartRed,
+    /// The green ring around the start button.
+    StartGreen,
+    /// Blink the button LEDs in their current colour.
+    BlinkOn,
+    BlinkOff,
+}
+
+impl Led {
+    pub const ALL: [Led; 11] = [
+        Led::BacklightOn,
+        Led::BacklightOff,
+        Led::ButtonAmber,
+        Led::ButtonGreen,
+        Led::ButtonAmberDim,
+        Led::ButtonGreenDim,
+        Led::ButtonOff,
+        Led::StartRed,
+        Led::StartGreen,
+        Led::BlinkOn,
+        Led::BlinkOff,
+    ];
+}
+
+impl Display for Led {
+    /// The argument of `SetLED` for this state.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let argument = match self {
+            Led::BacklightOn => "backlighton",
+            Led::BacklightOff => "backlightoff",
+            Led::ButtonAmber => "buttonamber",
+            Led::ButtonGreen => "buttongreen",
+            Led::ButtonAmberDim => "buttonamberdim",
+            Led::ButtonGreenDim => "buttongreendim",
+            Led::ButtonOff => "buttonoff",
+            Led::StartRed => "ledred",
+            Led::StartGreen => "ledgreen",
+            Led::BlinkOn => "blinkon",
+            Led::BlinkOff => "blinkoff",
+        };
+        write!(f, "{}", argument)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown LED state {0:?}")]
+pub struct UnknownLedError(String);
+
+impl FromStr for Led {
+    type Err = UnknownLedError;
+
+    /// Parse the `SetLED` argument, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Led::ALL
+            .iter()
+            .find(|led| led.to_string().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| UnknownLedError(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcdColor {
+    White,
+    Black,
+}
+
+/// The drawing operations of `SetLCD`. Lines span the whole screen and are drawn
+/// in the foreground colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcdCommand {
+    /// Fill the screen with a colour.
+    Background(LcdColor),
+    /// Colour for the lines drawn next.
+    Foreground(LcdColor),
+    HorizontalLine(u16),
+    VerticalLine(u16),
+    /// Alternating horizontal lines over the whole screen.
+    HorizontalBars,
+    /// Alternating vertical lines over the whole screen.
+    VerticalBars,
+    /// From 0 to 63.
+    Contrast(u8),
+}
+
+impl Display for LcdCommand {
+    /// The arguments of `SetLCD` for this operation.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LcdCommand::Background(LcdColor::White) => write!(f, "bgwhite"),
+            LcdCommand::Background(LcdColor::Black) => write!(f, "bgblack"),
+            LcdCommand::Foreground(LcdColor::White) => write!(f, "fgwhite"),
+            LcdCommand::Foreground(LcdColor::Black) => write!(f, "fgblack"),
+            LcdCommand::HorizontalLine(row) => write!(f, "hline {}", row),
+            LcdCommand::VerticalLine(column) => write!(f, "vline {}", column),
+            LcdCommand::HorizontalBars => write!(f, "hbars"),
+            LcdCommand::VerticalBars => write!(f, "vbars"),
+            LcdCommand::Contrast(contrast) => write!(f, "contrast {}", contrast),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid LCD command {0:?}")]
+pub struct InvalidLcdCommandError(String);
+
+impl FromStr for LcdCommand {
+    type Err = InvalidLcdCommandError;
+
+    /// Parse the `SetLCD` arguments, such as `bgwhite` or `hline 10`, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidLcdCommandError(s.to_string());
+        let lowercase = s.trim().to_lowercase();
+        let words: Vec<&str> = lowercase.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["bgwhite"] => LcdCommand::Background(LcdColor::White),
+            ["bgblack"] => LcdCommand::Background(LcdColor::Black),
+            ["fgwhite"] => LcdCommand::Foreground(LcdColor::White),
+            ["fgblack"] => LcdCommand::Foreground(LcdColor::Black),
+            ["hline", row] => LcdCommand::HorizontalLine(row.parse().map_err(|_| invalid())?),
+            ["vline", column] => LcdCommand::VerticalLine(column.parse().map_err(|_| invalid())?),
+            ["hbars"] => LcdCommand::HorizontalBars,
+            ["vbars"] => LcdCommand::VerticalBars,
+            ["contrast", contrast] => match contrast.parse() {
+                Ok(contrast) if contrast <= MAX_LCD_CONTRAST => LcdCommand::Contrast(contrast),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+        Ok(command)
+    }
+}
+
+pub const MAX_LCD_CONTRAST: u8 = 63;
+
+/// The robot's clock, which only knows the day of the week and the time of day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RobotTime {
+    pub weekday: Weekday,
+    pub time: NaiveTime,
+}
+
+impl RobotTime {
+    pub fn new(weekday: Weekday, time: NaiveTime) -> Self {
+        Self { weekday, time }
+    }
+
+    /// The robot time matching a date and time, such as `chrono::Local::now()`.
+    pub fn from_datetime<T: Datelike + Timelike>(datetime: &T) -> Self {
+        Self {
+            weekday: datetime.weekday(),
+            time: NaiveTime::from_hms_opt(datetime.hour(), datetime.minute(), datetime.second())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Seconds since the start of Sunday.
+    pub fn seconds_of_week(&self) -> u32 {
+        self.weekday.num_days_from_sunday() * 86400 + self.time.num_seconds_from_midnight()
+    }
+}
+
+impl Display for RobotTime {
+    /// The arguments of `SetTime` for this time.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "day {} hour {} min {} sec {}",
+            self.weekday.num_days_from_sunday(),
+            self.time.hour(),
+            self.time.minute(),
+            self.time.second()
+        )
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid robot time {0:?}")]
+pub struct InvalidTimeError(String);
+
+impl FromStr for RobotTime {
+    type Err = InvalidTimeError;
+
+    /// Parse a `GetTime` reply, such as `Sunday 13:57:09`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTimeError(s.to_string());
+        let mut words = s.split_whitespace();
+        let weekday = words
+            .next()
+            .and_then(|weekday| weekday.parse::<Weekday>().ok())
+            .ok_or_else(invalid)?;
+        let time = words
+            .next()
+            .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M:%S").ok())
+            .ok_or_else(invalid)?;
+        Ok(Self { weekday, time })
+    }
+}
+
+/// The cleaning behaviours `Clean` starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanMode {
+    House,
+    Spot,
+    Stop,
+}
+
+impl Display for CleanMode {
+    /// The argument of `Clean` for this mode.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CleanMode::House => write!(f, "house"),
+            CleanMode::Spot => write!(f, "spot"),
+            CleanMode::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+/// The power states `SetSystemMode` switches to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemMode {
+    Shutdown,
+    Hibernate,
+    Standby,
+    /// Turn off and on again.
+    PowerCycle,
+}
+
+impl Display for SystemMode {
+    /// The argument of `SetSystemMode` for this mode.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Toggle::On => String::from("on"),
-            Toggle::Off => String::from("off"),
+            SystemMode::Shutdown => write!(f, "shutdown"),
+            SystemMode::Hibernate => write!(f, "hibernate"),
+            SystemMode::Standby => write!(f, "standby"),
+            SystemMode::PowerCycle => write!(f, "powercycle"),
         }
     }
-    // add code here
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
 pub struct MotorStatus {
     brush_rpm: i32,
     brush_ma: i32,
@@ -39,48 +411,46 @@ pub struct MotorStatus {
     right_wheel_position_in_mm: i32,
     right_wheel_speed: i32,
     side_brush_ma: i32,
+    /// Fields this crate does not know, by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
 }
 
+const MOTOR_FIELDS: &[Field<MotorStatus>] = &[
+    field!("Brush_RPM", brush_rpm, required),
+    field!("Brush_mA", brush_ma),
+    field!("Vacuum_RPM", vacuum_rpm, required),
+    field!("Vacuum_mA", vacuum_ma),
+    field!("LeftWheel_RPM", left_wheel_rpm),
+    field!("LeftWheel_Load%", left_wheel_load),
+    field!(
+        "LeftWheel_PositionInMM",
+        left_wheel_position_in_mm,
+        required
+    ),
+    field!("LeftWheel_Speed", left_wheel_speed),
+    field!("RightWheel_RPM", right_wheel_rpm),
+    field!("RightWheel_Load%", right_wheel_load),
+    field!(
+        "RightWheel_PositionInMM",
+        right_wheel_position_in_mm,
+        required
+    ),
+    field!("RightWheel_Speed", right_wheel_speed),
+    field!("SideBrush_mA", side_brush_ma),
+];
+
 impl FromStr for MotorStatus {
-    // add code here
-    type Err = ParseIntError;
+    type Err = ParseReplyError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let lines: Vec<&str> = s.split('\n').collect();
-
-        let mut status = MotorStatus {
-            ..Default::default()
-        };
-
-        for line in lines {
-            log::debug!("line: {}", line);
-            if !line.is_empty() {
-                let field = IntField::from_str(line)?;
-                log::debug!("field: {:?}", field);
-                match field.name.as_str() {
-                    "Brush_RPM" => status.brush_rpm = field.value,
-                    "Brush_mA" => status.brush_ma = field.value,
-                    "Vacuum_RPM" => status.vacuum_rpm = field.value,
-                    "Vacuum_mA" => status.vacuum_ma = field.value,
-                    "LeftWheel_RPM" => status.left_wheel_rpm = field.value,
-                    "LeftWheel_Load%" => status.left_wheel_load = field.value,
-                    "LeftWheel_PositionInMM" => status.left_wheel_position_in_mm = field.value,
-                    "LeftWheel_Speed" => status.left_wheel_speed = field.value,
-                    "RightWheel_RPM" => status.right_wheel_rpm = field.value,
-                    "RightWheel_Load%" => status.right_wheel_load = field.value,
-                    "RightWheel_PositionInMM" => status.right_wheel_position_in_mm = field.value,
-                    "RightWheel_Speed" => status.right_wheel_speed = field.value,
-                    "SideBrush_mA" => status.side_brush_ma = field.value,
-                    _ => log::error!("Unrecognized field: {:?}", field),
-                }
-            }
-        }
-
+        let (mut status, extra) = fields::parse_fields(s, MOTOR_FIELDS)?;
+        status.extra = extra;
         Ok(status)
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct AnalogSensorStatus {
     battery_voltage: f32,
     battery_current: f32,
@@ -96,48 +466,39 @@ pub struct AnalogSensorStatus {
     wall_sensor: f32,
     drop_sensor_left: f32,
     drop_sensor_right: f32,
+    /// Fields this crate does not know, by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
 }
 
+const ANALOG_SENSOR_FIELDS: &[Field<AnalogSensorStatus>] = &[
+    field!("BatteryVoltage", battery_voltage, required),
+    field!("BatteryCurrent", battery_current),
+    field!("BatteryTemperature", battery_temperature),
+    field!("ExternalVoltage", external_voltage),
+    field!("AccelerometerX", accelerometer_x),
+    field!("AccelerometerY", accelerometer_y),
+    field!("AccelerometerZ", accelerometer_z),
+    field!("VacuumCurrent", vacuum_current),
+    field!("SideBrushCurrent", side_brush_current),
+    field!("MagSensorLeft", mag_sensor_left),
+    field!("MagSensorRight", mag_sensor_right),
+    field!("WallSensor", wall_sensor),
+    field!("DropSensorLeft", drop_sensor_left),
+    field!("DropSensorRight", drop_sensor_right),
+];
+
 impl FromStr for AnalogSensorStatus {
-    type Err = ParseFloatError;
+    type Err = ParseReplyError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let lines: Vec<&str> = s.split('\n').collect();
-
-        let mut status = AnalogSensorStatus {
-            ..Default::default()
-        };
-
-        for line in lines {
-            log::debug!("line: {}", line);
-            if !line.is_empty() {
-                let field = UnitFloatField::from_str(line)?;
-                log::debug!("{:?}", field);
-                match field.name.as_str() {
-                    "BatteryVoltage" => status.battery_voltage = field.value,
-                    "BatteryCurrent" => status.battery_current = field.value,
-                    "BatteryTemperature" => status.battery_temperature = field.value,
-                    "ExternalVoltage" => status.external_voltage = field.value,
-                    "AccelerometerX" => status.accelerometer_x = field.value,
-                    "AccelerometerY" => status.accelerometer_y = field.value,
-                    "AccelerometerZ" => status.accelerometer_z = field.value,
-                    "VacuumCurrent" => status.vacuum_current = field.value,
-                    "SideBrushCurrent" => status.side_brush_current = field.value,
-                    "MagSensorLeft" => status.mag_sensor_left = field.value,
-                    "MagSensorRight" => status.mag_sensor_right = field.value,
-                    "WallSensor" => status.wall_sensor = field.value,
-                    "DropSensorLeft" => status.drop_sensor_left = field.value,
-                    "DropSensorRight" => status.drop_sensor_right = field.value,
-                    _ => log::error!("Unrecognized field: {:?}", field),
-                }
-            }
-        }
-
+        let (mut status, extra) = fields::parse_fields(s, ANALOG_SENSOR_FIELDS)?;
+        status.extra = extra;
         Ok(status)
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct DigitalSensorStatus {
     sensor_dc_jack_is_in: bool,
     sensor_dustbin_is_in: bool,
@@ -149,44 +510,43 @@ pub struct DigitalSensorStatus {
     right_sidebit: bool,
     right_frontbit: bool,
     right_ldsbit: bool,
+    /// Fields this crate does not know, by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
 }
 
+const DIGITAL_SENSOR_FIELDS: &[Field<DigitalSensorStatus>] = &[
+    field!("SNSR_DC_JACK_IS_IN", sensor_dc_jack_is_in, required),
+    field!("SNSR_DUSTBIN_IS_IN", sensor_dustbin_is_in, required),
+    field!(
+        "SNSR_LEFT_WHEEL_EXTENDED",
+        sensor_left_wheel_extended,
+        required
+    ),
+    field!(
+        "SNSR_RIGHT_WHEEL_EXTENDED",
+        sensor_right_wheel_extended,
+        required
+    ),
+    field!("LSIDEBIT", left_sidebit),
+    field!("LFRONTBIT", left_frontbit),
+    field!("LLDSBIT", left_ldsbit),
+    field!("RSIDEBIT", right_sidebit),
+    field!("RFRONTBIT", right_frontbit),
+    field!("RLDSBIT", right_ldsbit),
+];
+
 impl FromStr for DigitalSensorStatus {
-    type Err = ParseIntError;
+    type Err = ParseReplyError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let lines: Vec<&str> = s.split('\n').collect();
-
-        let mut status = DigitalSensorStatus {
-            ..Default::default()
-        };
-
-        for line in lines {
-            log::debug!("line: {}", line);
-            if !line.is_empty() {
-                let field = BoolField::from_str(line)?;
-                log::debug!("{:?}", field);
-                match field.name.as_str() {
-                    "SNSR_DC_JACK_IS_IN" => status.sensor_dc_jack_is_in = field.value,
-                    "SNSR_DUSTBIN_IS_IN" => status.sensor_dustbin_is_in = field.value,
-                    "SNSR_LEFT_WHEEL_EXTENDED" => status.sensor_left_wheel_extended = field.value,
-                    "SNSR_RIGHT_WHEEL_EXTENDED" => status.sensor_right_wheel_extended = field.value,
-                    "LSIDEBIT" => status.left_sidebit = field.value,
-                    "LFRONTBIT" => status.left_frontbit = field.value,
-                    "LLDSBIT" => status.left_ldsbit = field.value,
-                    "RSIDEBIT" => status.right_sidebit = field.value,
-                    "RFRONTBIT" => status.right_frontbit = field.value,
-                    "RLDSBIT" => status.right_ldsbit = field.value,
-                    _ => log::error!("Unrecognized field: {:?}", field),
-                }
-            }
-        }
-
+        let (mut status, extra) = fields::parse_fields(s, DIGITAL_SENSOR_FIELDS)?;
+        status.extra = extra;
         Ok(status)
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct ChargerStatus {
     fuel_percent: i32,
     battery_over_tmp: i32,
@@ -203,59 +563,144 @@ pub struct ChargerStatus {
     v_ext_v: f32,
     charger_mah: i32,
     discharge_mah: i32,
+    /// Fields this crate does not know, by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
 }
 
+const CHARGER_FIELDS: &[Field<ChargerStatus>] = &[
+    field!("FuelPercent", fuel_percent, required),
+    field!("BatteryOverTemp", battery_over_tmp),
+    field!("ChargingActive", charging_active, required),
+    field!("ChargingEnabled", charging_enabled),
+    field!("ConfidentOnFuel", confident_on_fuel),
+    field!("OnReservedFuel", on_reserved_fuel),
+    field!("EmptyFuel", empty_fuel),
+    field!("BatteryFailure", battery_failure),
+    field!("ExtPwrPresent", ext_pwr_present, required),
+    field!("ThermistorPresent", thermistor_present),
+    field!("BattTempCAvg", batt_temp_c_avg),
+    field!("VBattV", v_batt_v_v, required),
+    field!("VExtV", v_ext_v),
+    field!("Charger_mAH", charger_mah),
+    field!("Discharge_mAH", discharge_mah),
+];
+
 impl FromStr for ChargerStatus {
-    type Err = ParseNumberError;
+    type Err = ParseReplyError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let lines: Vec<&str> = s.split('\n').collect();
+        let (mut status, extra) = fields::parse_fields(s, CHARGER_FIELDS)?;
+        status.extra = extra;
+        Ok(status)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct VersionComponent {
+    pub component: String,
+    pub major: String,
+    pub minor: String,
+    pub build: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Version {
+    pub components: Vec<VersionComponent>,
+}
+
+impl FromStr for Version {
+    type Err = std::convert::Infallible;
 
-        let mut status = ChargerStatus {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut version = Version {
             ..Default::default()
         };
 
-        for line in lines {
+        for line in s.lines() {
             log::debug!("line: {}", line);
-            if !line.is_empty() {
-                let _ = match IntField::from_str(&line) {
-                    Ok(field) => {
-                        log::debug!("{:?}", field);
-                        match field.name.as_str() {
-                            "FuelPercent" => status.fuel_percent = field.value,
-                            "BatteryOverTemp" => status.battery_over_tmp = field.value,
-                            "ChargingActive" => status.charging_active = field.value,
-                            "ChargingEnabled" => status.charging_enabled = field.value,
-                            "ConfidentOnFuel" => status.confident_on_fuel = field.value,
-                            "OnReservedFuel" => status.on_reserved_fuel = field.value,
-                            "EmptyFuel" => status.empty_fuel = field.value,
-                            "BatteryFailure" => status.battery_failure = field.value,
-                            "ExtPwrPresent" => status.ext_pwr_present = field.value,
-                            "ThermistorPresent" => status.thermistor_present = field.value,
-                            "BattTempCAvg" => status.batt_temp_c_avg = field.value,
-                            "Charger_mAH" => status.charger_mah = field.value,
-                            "Discharge_mAH" => status.discharge_mah = field.value,
-                            _ => log::error!("Unrecognized field: {:?}", field),
-                        }
-                    }
-                    Err(_parse_int_error) => {
-                        let _ = match SimpleFloatField::from_str(&line) {
-                            Ok(field) => match field.name.as_str() {
-                                "VBattV" => status.v_batt_v_v = field.value,
-                                "VExtV" => status.v_ext_v = field.value,
-                                _ => log::error!("Unrecognized field: {:?}", field),
-                            },
-                            Err(err) => return Err(ParseNumberError::ParseFloat(err)),
-                        };
-                    }
-                };
-            };
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+            if fields.len() < 2 || fields[0] == "Component" {
+                continue;
+            }
+            let field = |index: usize| String::from(*fields.get(index).unwrap_or(&""));
+            version.components.push(VersionComponent {
+                component: field(0),
+                major: field(1),
+                minor: field(2),
+                build: field(3),
+            });
         }
 
-        Ok(status)
+        Ok(version)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct LdsReading {
+    /// Degrees, counter-clockwise from the front of the robot.
+    pub angle: u16,
+    pub distance_mm: u32,
+    pub intensity: u32,
+    /// Zero for a valid reading.
+    pub error_code: u16,
+}
+
+impl LdsReading {
+    pub fn is_valid(&self) -> bool {
+        self.error_code == 0
+    }
+}
+
+/// A full `getldsscan` reply.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LdsScan {
+    pub readings: Vec<LdsReading>,
+    /// Turret speed in Hz, when the reply included it.
+    pub rotation_speed: Option<f32>,
+}
+
+impl FromStr for LdsScan {
+    type Err = ParseNumberError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut scan = LdsScan {
+            ..Default::default()
+        };
+
+        for line in s.lines() {
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+            match fields.as_slice() {
+                ["ROTATION_SPEED", speed] => {
+                    scan.rotation_speed = Some(speed.parse().map_err(ParseNumberError::ParseFloat)?)
+                }
+                [angle, distance, intensity, error_code] if *angle != "AngleInDegrees" => {
+                    scan.readings.push(LdsReading {
+                        angle: angle.parse().map_err(ParseNumberError::ParseInt)?,
+                        distance_mm: distance.parse().map_err(ParseNumberError::ParseInt)?,
+                        intensity: intensity.parse().map_err(ParseNumberError::ParseInt)?,
+                        error_code: u16::from_str_radix(error_code, 16)
+                            .map_err(ParseNumberError::ParseInt)?,
+                    })
+                }
+                _ => log::debug!("Skipping scan line: {}", line),
+            }
+        }
+
+        Ok(scan)
     }
 }
 
+/// Marks the end of every reply from the robot (Ctrl-Z).
+const END_OF_REPLY: u8 = 0x1a;
+
+/// Upper bound on the size of a single reply, in case the end marker is lost.
+const MAX_REPLY_LENGTH: usize = 65536;
+
+/// Failed reads after which `set_testmode` stops waiting for its echo, so an
+/// unresponsive robot cannot hang a shutdown.
+const MAX_SYNC_ERRORS: usize = 10;
+
 pub trait NeatoRobot {
     fn exit(&mut self) -> Result<()>;
     fn set_testmode(&mut self, value: Toggle) -> Result<()>;
@@ -263,6 +708,8 @@ pub trait NeatoRobot {
 
     fn request_scan(&mut self) -> Result<()>;
     fn get_scan_ranges(&mut self) -> Result<Vec<f32>>;
+    /// Request a scan and return every reading, with intensities and error codes.
+    fn get_lds_scan(&mut self) -> Result<LdsScan>;
 
     fn set_motors(&mut self, left_distance: i32, right_distance: i32, speed: i32) -> Result<()>;
     fn get_motors(&mut self) -> Result<MotorStatus>;
@@ -272,13 +719,107 @@ pub trait NeatoRobot {
     fn get_charger(&mut self) -> Result<ChargerStatus>;
 
     fn set_backlight(&mut self, value: Toggle) -> Result<()>;
+    /// Set an LED and check that the firmware accepted it. Needs test mode.
+    fn set_led(&mut self, led: Led) -> Result<()>;
+
+    /// Play a sound from the firmware's library, or stop the one playing.
+    fn play_sound(&mut self, sound: SoundId) -> Result<()>;
+
+    /// Draw on the LCD. Needs test mode.
+    fn set_lcd(&mut self, command: LcdCommand) -> Result<()>;
+
+    fn get_time(&mut self) -> Result<RobotTime>;
+    fn set_time(&mut self, time: &RobotTime) -> Result<()>;
+
+    fn get_schedule(&mut self) -> Result<Schedule>;
+    /// Replace the schedule of every day and enable or disable it.
+    fn set_schedule(&mut self, schedule: &Schedule) -> Result<()>;
+    fn set_schedule_enabled(&mut self, value: Toggle) -> Result<()>;
+
+    /// Start or stop the firmware's own cleaning. Fails in test mode, and leaves test
+    /// mode first when it is not known to be off.
+    fn clean(&mut self, mode: CleanMode) -> Result<()>;
+    /// Whether a cleaning cycle is running, judged by the brush and vacuum motors.
+    fn is_cleaning(&mut self) -> Result<bool>;
+
+    /// Stop the motors and LDS, leave test mode and switch the power state.
+    fn set_system_mode(&mut self, mode: SystemMode) -> Result<()>;
+
+    /// The error the firmware is reporting, if any.
+    fn get_error(&mut self) -> Result<Option<RobotError>>;
+    /// Dismiss the error the firmware is reporting.
+    fn clear_error(&mut self) -> Result<()>;
+    fn get_sys_log(&mut self) -> Result<SysLog>;
+
+    /// Lifetime cleaning time and count, see `usage::get_usage` for wear estimates.
+    fn get_warranty(&mut self) -> Result<Warranty>;
+
+    fn get_cal_info(&mut self) -> Result<CalInfo>;
+    /// Overwrite a drop or wall sensor calibration with the sensor's current reading.
+    /// Needs test mode and the calibration target in place.
+    fn set_distance_cal(&mut self, point: DistanceCalPoint) -> Result<()>;
+
+    fn get_version(&mut self) -> Result<Version>;
+
+    /// Send any command and return its reply, without the echoed command.
+    fn command(&mut self, command: &str) -> Result<String>;
+    /// Send any command and read its reply as a table, to explore commands this
+    /// crate does not know.
+    fn raw_command(&mut self, command: &str) -> Result<Table>;
 
     fn read_line(&mut self) -> Result<String>;
     fn read_lines(&mut self, line_count: i32) -> Result<String>;
+    fn read_reply(&mut self) -> Result<String>;
+}
+
+/// When `set_ldsrotation` considers the laser turret up to speed.
+#[derive(Debug, Clone, Copy)]
+pub struct SpinUpConfig {
+    /// Lowest acceptable rotation speed, in Hz.
+    pub min_speed: f32,
+    /// Highest acceptable rotation speed, in Hz.
+    pub max_speed: f32,
+    /// Consecutive readings inside the band before the speed counts as settled.
+    pub settle_count: usize,
+    pub poll_interval: time::Duration,
+    pub timeout: time::Duration,
+}
+
+impl Default for SpinUpConfig {
+    fn default() -> Self {
+        // The turret is regulated to 5 Hz
+        Self {
+            min_speed: 4.5,
+            max_speed: 5.5,
+            settle_count: 3,
+            poll_interval: time::Duration::from_millis(100),
+            timeout: time::Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LdsError {
+    #[error(
+        "LDS did not settle between {min_speed} and {max_speed} Hz within {timeout:?}, {}",
+        match .last_speed {
+            Some(speed) => format!("last speed was {} Hz", speed),
+            None => String::from("it never reported a speed"),
+        }
+    )]
+    SpinUpTimeout {
+        min_speed: f32,
+        max_speed: f32,
+        timeout: time::Duration,
+        last_speed: Option<f32>,
+    },
 }
 
 pub struct DSeries<'a> {
     serial_port: Box<dyn SerialPort + 'a>,
+    lds_spin_up: SpinUpConfig,
+    /// Whether test mode is on, as far as we know.
+    testmode: Option<bool>,
     motor_status: MotorStatus,
     analog_sensor_status: AnalogSensorStatus,
     digital_sensor_status: DigitalSensorStatus,
@@ -301,7 +842,9 @@ impl Display for DSeries<'_> {
 impl DSeries<'_> {
     pub fn new(serial_port: Box<dyn SerialPort>) -> Self {
         Self {
-            serial_port: serial_port,
+            serial_port,
+            lds_spin_up: SpinUpConfig::default(),
+            testmode: None,
             motor_status: MotorStatus {
                 ..Default::default()
             },
@@ -316,6 +859,111 @@ impl DSeries<'_> {
             },
         }
     }
+
+    /// The last status read by `get_motors`.
+    pub fn motor_status(&self) -> &MotorStatus {
+        &self.motor_status
+    }
+
+    /// The last status read by `get_analog_sensors`.
+    pub fn analog_sensor_status(&self) -> &AnalogSensorStatus {
+        &self.analog_sensor_status
+    }
+
+    /// The last status read by `get_digital_sensors`.
+    pub fn digital_sensor_status(&self) -> &DigitalSensorStatus {
+        &self.digital_sensor_status
+    }
+
+    /// The last status read by `get_charger`.
+    pub fn charger_status(&self) -> &ChargerStatus {
+        &self.charger_status
+    }
+
+    /// Change when `set_ldsrotation` considers the turret up to speed.
+    pub fn set_lds_spin_up(&mut self, config: SpinUpConfig) {
+        self.lds_spin_up = config;
+    }
+
+    /// Poll the LDS rotation speed until it settles inside the configured band,
+    /// returning the last speed.
+    pub fn wait_for_lds_spin_up(&mut self) -> Result<f32> {
+        let config = self.lds_spin_up;
+        let start = time::Instant::now();
+        let mut last_speed = None;
+        let mut settled = 0;
+
+        while start.elapsed() < config.timeout {
+            let speed = match self.get_lds_scan() {
+                Ok(scan) => scan.rotation_speed,
+                // The turret can garble a scan while it speeds up
+                Err(err) if err.is::<ParseNumberError>() => {
+                    log::debug!("Unreadable scan while spinning up: {}", err);
+                    None
+                }
+                Err(err) => return Err(err),
+            };
+            match speed {
+                Some(speed) => {
+                    log::debug!("LDS rotation speed {} Hz", speed);
+                    last_speed = Some(speed);
+                    if speed >= config.min_speed && speed <= config.max_speed {
+                        settled += 1;
+                        if settled >= config.settle_count {
+                            log::info!("LDS up to speed after {:?}", start.elapsed());
+                            return Ok(speed);
+                        }
+                    } else {
+                        settled = 0;
+                    }
+                }
+                None => {
+                    log::debug!("No LDS rotation speed in scan");
+                    settled = 0;
+                }
+            }
+            thread::sleep(config.poll_interval);
+        }
+
+        Err(LdsError::SpinUpTimeout {
+            min_speed: config.min_speed,
+            max_speed: config.max_speed,
+            timeout: config.timeout,
+            last_speed,
+        }
+        .into())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Robot rejected {command:?}: {reply}")]
+    Rejected { command: String, reply: String },
+    #[error("{command:?} does not work in test mode")]
+    TestModeOn { command: String },
+    #[error("{command:?} only works in test mode")]
+    TestModeOff { command: String },
+}
+
+/// Words with which the firmware answers a command it did not carry out.
+const REJECTIONS: [&str; 4] = ["unknown", "invalid", "unrecognized", "testmode"];
+
+/// Commands that change state reply with nothing, or with help text or an error
+/// when they were not accepted.
+fn check_accepted(command: &str, reply: &str) -> Result<(), CommandError> {
+    let reply = reply.trim();
+    let lowercase = reply.to_lowercase();
+    if REJECTIONS.iter().any(|word| lowercase.contains(word)) {
+        return Err(CommandError::Rejected {
+            command: command.to_string(),
+            reply: reply.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn is_timeout(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<io::Error>(), Some(err) if err.kind() == io::ErrorKind::TimedOut)
 }
 
 #[derive(Error, Debug)]
@@ -374,11 +1022,6 @@ struct IntField {
     value: i32,
 }
 #[derive(Debug, PartialEq, Default)]
-struct BoolField {
-    name: String,
-    value: bool,
-}
-#[derive(Debug, PartialEq, Default)]
 struct SimpleFloatField {
     name: String,
     value: f32,
@@ -394,9 +1037,9 @@ impl FromStr for UnitFloatField {
         let value = fields[2].trim().parse::<f32>()?;
 
         Ok(UnitFloatField {
-            name: name,
-            unit: unit,
-            value: value,
+            name,
+            unit,
+            value,
         })
     }
 }
@@ -413,26 +1056,8 @@ impl FromStr for IntField {
         let value = fields[1].trim().parse::<i32>()?;
 
         Ok(IntField {
-            name: name,
-            value: value,
-        })
-    }
-}
-
-impl FromStr for BoolField {
-    type Err = ParseIntError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let fields: Vec<&str> = s.split(',').collect();
-
-        log::debug!("{:?}", fields);
-
-        let name = String::from(fields[0]);
-        let value = fields[1].trim().parse::<i32>()?;
-
-        Ok(BoolField {
-            name: name,
-            value: value == 1,
+            name,
+            value,
         })
     }
 }
@@ -446,8 +1071,8 @@ impl FromStr for SimpleFloatField {
         let value = fields[1].trim().parse::<f32>()?;
 
         Ok(SimpleFloatField {
-            name: name,
-            value: value,
+            name,
+            value,
         })
     }
 }
@@ -462,17 +1087,22 @@ impl NeatoRobot for DSeries<'_> {
 
     fn set_testmode(&mut self, value: Toggle) -> Result<()> {
         log::debug!("Setting testmode");
-        writeln!(self.serial_port, "testmode {}", value.to_string())
+        writeln!(self.serial_port, "testmode {}", value)
             .context("Could not write to serial port")?;
 
+        let mut errors = 0;
         loop {
             let s = match self.read_line() {
                 Ok(v) => {
                     log::debug!("{}", v);
                     v
                 }
-                Err(_) => {
+                Err(err) => {
                     log::error!("Error reading back");
+                    errors += 1;
+                    if errors >= MAX_SYNC_ERRORS {
+                        return Err(err.context("Robot did not acknowledge testmode"));
+                    }
                     String::new()
                 }
             };
@@ -485,23 +1115,29 @@ impl NeatoRobot for DSeries<'_> {
         }
 
         self.serial_port.flush()?;
+        self.testmode = Some(matches!(value, Toggle::On));
         log::debug!("Set testmode");
         Ok(())
     }
 
     fn set_ldsrotation(&mut self, value: Toggle) -> Result<()> {
         log::debug!("Setting ldsrotation");
-        writeln!(self.serial_port, "setldsrotation {}", value.to_string())?;
+        writeln!(self.serial_port, "setldsrotation {}", value)?;
 
-        let _s = match self.read_line() {
+        match self.read_line() {
             Ok(v) => log::debug!("{}", v),
             Err(_) => log::error!("Error reading back"),
         };
 
         self.serial_port.flush()?;
         log::debug!("Set ldsrotation");
-        thread::sleep(time::Duration::from_millis(5000));
-        log::info!("Wait for laser turret to spin up to speed");
+        match value {
+            Toggle::On => {
+                log::info!("Wait for laser turret to spin up to speed");
+                self.wait_for_lds_spin_up()?;
+            }
+            Toggle::Off => {}
+        }
         Ok(())
     }
 
@@ -509,7 +1145,7 @@ impl NeatoRobot for DSeries<'_> {
         log::debug!("Requesting scan");
         writeln!(self.serial_port, "getldsscan")?;
 
-        let _s = match self.read_line() {
+        match self.read_line() {
             Ok(v) => log::debug!("{}", v),
             Err(_) => log::error!("Error reading back"),
         };
@@ -536,7 +1172,7 @@ impl NeatoRobot for DSeries<'_> {
         }
 
         let s = String::from_utf8(longbuffer)?;
-        return Ok(s);
+        Ok(s)
     }
 
     fn read_lines(&mut self, line_count: i32) -> Result<String> {
@@ -562,6 +1198,27 @@ impl NeatoRobot for DSeries<'_> {
         Ok(joined)
     }
 
+    fn read_reply(&mut self) -> Result<String> {
+        let mut reply = vec![];
+
+        loop {
+            let mut buffer = [0; 1];
+            let _n = self.serial_port.read(&mut buffer)?;
+            let ch = buffer[0];
+            if ch == END_OF_REPLY {
+                break;
+            }
+            reply.push(ch);
+            if reply.len() >= MAX_REPLY_LENGTH {
+                log::error!("No end of reply after {} bytes", reply.len());
+                break;
+            }
+        }
+
+        let s = String::from_utf8(reply)?;
+        Ok(s)
+    }
+
     fn get_scan_ranges(&mut self) -> Result<Vec<f32>> {
         log::debug!("Reading serial_port for scan_ranges");
 
@@ -602,7 +1259,7 @@ impl NeatoRobot for DSeries<'_> {
             left_distance, right_distance, speed
         )?;
 
-        let _s = match self.read_line() {
+        match self.read_line() {
             Ok(v) => log::debug!("{}", v),
             Err(_) => log::error!("Error reading back"),
         };
@@ -614,134 +1271,359 @@ impl NeatoRobot for DSeries<'_> {
 
     fn get_motors(&mut self) -> Result<MotorStatus> {
         log::debug!("get_motors");
+        let reply = self.command("getmotors")?;
+        let status = MotorStatus::from_str(&reply)?;
+        self.motor_status = status.clone();
+        log::debug!("Got motors");
+        Ok(status)
+    }
 
-        writeln!(self.serial_port, "getmotors\n")?;
+    fn get_analog_sensors(&mut self) -> Result<AnalogSensorStatus> {
+        log::debug!("get_analog_sensors");
+        let reply = self.command("getanalogsensors")?;
+        let status = AnalogSensorStatus::from_str(&reply)?;
+        self.analog_sensor_status = status.clone();
+        log::debug!("Got analog_sensors");
+        Ok(status)
+    }
 
-        let _s = match self.read_line() {
-            Ok(v) => log::debug!("{}", v),
-            Err(_) => log::error!("Error reading back"),
-        };
+    fn get_digital_sensors(&mut self) -> Result<DigitalSensorStatus> {
+        log::debug!("get_digital_sensors");
+        let reply = self.command("getdigitalsensors")?;
+        let status = DigitalSensorStatus::from_str(&reply)?;
+        self.digital_sensor_status = status.clone();
+        log::debug!("Got digital_sensors");
+        Ok(status)
+    }
 
-        self.serial_port.flush()?;
+    fn get_charger(&mut self) -> Result<ChargerStatus> {
+        log::debug!("get_charger");
+        let reply = self.command("getcharger")?;
+        let status = ChargerStatus::from_str(&reply)?;
+        self.charger_status = status.clone();
+        log::debug!("Got charger");
+        Ok(status)
+    }
 
-        log::debug!("Serial port flushed");
+    fn get_lds_scan(&mut self) -> Result<LdsScan> {
+        log::debug!("get_lds_scan");
+        let reply = self.command("getldsscan")?;
+        let scan = LdsScan::from_str(&reply)?;
+        log::debug!("Got LDS scan");
+        Ok(scan)
+    }
 
-        log::debug!("Reading values...");
-        loop {
-            let header = self.read_line()?;
-            log::debug!("{}", header);
+    fn get_version(&mut self) -> Result<Version> {
+        log::debug!("get_version");
+        let reply = self.command("getversion")?;
+        let version = Version::from_str(&reply)?;
+        log::debug!("Got version");
+        Ok(version)
+    }
 
-            if header.contains("Parameter,Value") {
-                break;
-            }
+    fn command(&mut self, command: &str) -> Result<String> {
+        log::debug!("command({})", command);
+
+        // Drop whatever is left of earlier replies, so it is not mistaken for this one
+        self.serial_port.clear(ClearBuffer::Input)?;
+        writeln!(self.serial_port, "{}", command).context("Could not write to serial port")?;
+        self.serial_port.flush()?;
+
+        let reply = self.read_reply()?;
+        log::debug!("Got reply: {}", reply);
+
+        let mut lines = reply.splitn(2, '\n');
+        let first = lines.next().unwrap_or("");
+        if first.trim().eq_ignore_ascii_case(command.trim()) {
+            Ok(String::from(lines.next().unwrap_or("")))
+        } else {
+            Ok(reply)
         }
+    }
 
-        let lines = self.read_lines(13)?;
-        log::debug!("Got {} lines", lines);
-        let status = MotorStatus::from_str(&lines)?;
+    fn raw_command(&mut self, command: &str) -> Result<Table> {
+        log::debug!("raw_command({})", command);
+        let reply = self.command(command)?;
+        let table = Table::from_str(&reply)?;
+        log::debug!("Got {} rows", table.rows.len());
+        Ok(table)
+    }
 
-        self.motor_status = status;
-        log::debug!("Got motors");
-        Ok(status)
+    fn set_backlight(&mut self, value: Toggle) -> Result<()> {
+        match value {
+            Toggle::On => self.set_led(Led::BacklightOn),
+            Toggle::Off => self.set_led(Led::BacklightOff),
+        }
     }
 
-    fn get_analog_sensors(&mut self) -> Result<AnalogSensorStatus> {
-        log::debug!("get_analog_sensors");
+    fn set_led(&mut self, led: Led) -> Result<()> {
+        log::debug!("set_led({:?})", led);
+        let command = format!("setled {}", led);
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        log::debug!("Set LED");
+        Ok(())
+    }
 
-        writeln!(self.serial_port, "getanalogsensors\n")?;
+    fn play_sound(&mut self, sound: SoundId) -> Result<()> {
+        log::debug!("play_sound({:?})", sound);
+        let command = format!("playsound {}", sound);
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        log::debug!("Played sound");
+        Ok(())
+    }
 
-        let _s = match self.read_line() {
-            Ok(v) => log::debug!("{}", v),
-            Err(_) => log::error!("Error reading back"),
+    fn set_lcd(&mut self, command: LcdCommand) -> Result<()> {
+        log::debug!("set_lcd({:?})", command);
+        let command = match command {
+            LcdCommand::Contrast(contrast) => LcdCommand::Contrast(contrast.min(MAX_LCD_CONTRAST)),
+            command => command,
         };
+        let command = format!("setlcd {}", command);
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        log::debug!("Set LCD");
+        Ok(())
+    }
 
-        self.serial_port.flush()?;
+    fn get_time(&mut self) -> Result<RobotTime> {
+        log::debug!("get_time");
+        let reply = self.command("gettime")?;
+        let time = RobotTime::from_str(reply.trim())?;
+        log::debug!("Got time");
+        Ok(time)
+    }
 
-        log::debug!("Serial port flushed");
+    fn set_time(&mut self, time: &RobotTime) -> Result<()> {
+        log::debug!("set_time({:?})", time);
+        let command = format!("settime {}", time);
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        log::debug!("Set time");
+        Ok(())
+    }
 
-        log::debug!("Reading values...");
-        loop {
-            let header = self.read_line()?;
-            log::debug!("{}", header);
+    fn get_schedule(&mut self) -> Result<Schedule> {
+        log::debug!("get_schedule");
+        let reply = self.command("getschedule")?;
+        let schedule = Schedule::from_str(&reply)?;
+        log::debug!("Got schedule");
+        Ok(schedule)
+    }
 
-            if header.contains("SensorName,Unit,Value") {
-                break;
-            }
+    fn set_schedule(&mut self, schedule: &Schedule) -> Result<()> {
+        log::debug!("set_schedule({:?})", schedule);
+        for day in schedule::DAYS.iter() {
+            let command = match schedule.entry(*day) {
+                Some(entry) => format!("setschedule {}", entry.arguments()),
+                None => format!(
+                    "setschedule day {} hour 0 min 0 none",
+                    day.num_days_from_sunday()
+                ),
+            };
+            let reply = self.command(&command)?;
+            check_accepted(&command, &reply)?;
         }
+        self.set_schedule_enabled(if schedule.enabled {
+            Toggle::On
+        } else {
+            Toggle::Off
+        })?;
+        log::debug!("Set schedule");
+        Ok(())
+    }
 
-        let lines = self.read_lines(14)?;
-        log::debug!("Got {} lines", lines);
-        let status = AnalogSensorStatus::from_str(&lines)?;
+    fn set_schedule_enabled(&mut self, value: Toggle) -> Result<()> {
+        log::debug!("set_schedule_enabled({})", value);
+        let command = format!("setschedule {}", value);
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        Ok(())
+    }
 
-        self.analog_sensor_status = status;
-        log::debug!("Got analog_sensors");
-        Ok(status)
+    fn clean(&mut self, mode: CleanMode) -> Result<()> {
+        log::debug!("clean({})", mode);
+        let command = format!("clean {}", mode);
+        match self.testmode {
+            Some(true) => return Err(CommandError::TestModeOn { command }.into()),
+            // An earlier session may have left test mode on
+            None => self.set_testmode(Toggle::Off)?,
+            Some(false) => {}
+        }
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        log::debug!("Cleaning {}", mode);
+        Ok(())
     }
 
-    fn get_digital_sensors(&mut self) -> Result<DigitalSensorStatus> {
-        log::debug!("get_digital_sensors");
+    fn is_cleaning(&mut self) -> Result<bool> {
+        let motors = self.get_motors()?;
+        Ok(motors.brush_rpm > 0 || motors.vacuum_rpm > 0)
+    }
 
-        writeln!(self.serial_port, "getdigitalsensors\n")?;
+    fn set_system_mode(&mut self, mode: SystemMode) -> Result<()> {
+        log::debug!("set_system_mode({})", mode);
+        guard::restore(self)?;
 
-        let _s = match self.read_line() {
-            Ok(v) => log::debug!("{}", v),
-            Err(_) => log::error!("Error reading back"),
-        };
+        let command = format!("setsystemmode {}", mode);
+        match self.command(&command) {
+            Ok(reply) => check_accepted(&command, &reply)?,
+            // The robot may power down before it finishes its reply
+            Err(err) if is_timeout(&err) => log::debug!("No reply to {:?}", command),
+            Err(err) => return Err(err),
+        }
+        log::info!("Switched robot to {}", mode);
+        Ok(())
+    }
 
-        self.serial_port.flush()?;
+    fn get_error(&mut self) -> Result<Option<RobotError>> {
+        log::debug!("get_error");
+        let reply = self.command("geterr")?;
+        let error = RobotError::parse(&reply);
+        log::debug!("Got error {:?}", error);
+        Ok(error)
+    }
 
-        log::debug!("Serial port flushed");
+    fn clear_error(&mut self) -> Result<()> {
+        log::debug!("clear_error");
+        let command = "geterr clear";
+        let reply = self.command(command)?;
+        check_accepted(command, &reply)?;
+        Ok(())
+    }
 
-        log::debug!("Reading values...");
-        loop {
-            let header = self.read_line()?;
-            log::debug!("{}", header);
+    fn get_sys_log(&mut self) -> Result<SysLog> {
+        log::debug!("get_sys_log");
+        let reply = self.command("getsyslog")?;
+        let log = SysLog::from_str(&reply)?;
+        log::debug!("Got {} system log entries", log.entries.len());
+        Ok(log)
+    }
 
-            if header.contains("Digital Sensor Name, Value") {
-                break;
-            }
-        }
+    fn get_warranty(&mut self) -> Result<Warranty> {
+        log::debug!("get_warranty");
+        let reply = self.command("getwarranty")?;
+        let warranty = Warranty::from_str(&reply)?;
+        log::debug!("Got warranty");
+        Ok(warranty)
+    }
 
-        let lines = self.read_lines(10)?;
-        let status = DigitalSensorStatus::from_str(&lines)?;
-        self.digital_sensor_status = status;
-        log::debug!("Got digital_sensors");
-        Ok(status)
+    fn get_cal_info(&mut self) -> Result<CalInfo> {
+        log::debug!("get_cal_info");
+        let reply = self.command("getcalinfo")?;
+        let info = CalInfo::from_str(&reply)?;
+        log::debug!("Got calibration");
+        Ok(info)
     }
 
-    fn get_charger(&mut self) -> Result<ChargerStatus> {
-        log::debug!("get_charger");
+    fn set_distance_cal(&mut self, point: DistanceCalPoint) -> Result<()> {
+        log::debug!("set_distance_cal({})", point);
+        let command = format!("setdistancecal {}", point);
+        // Refuse early, the firmware's complaint is easy to miss. Unlike `clean`, do
+        // not turn test mode on when its state is unknown, the target may not be in place.
+        match self.testmode {
+            Some(true) => {}
+            Some(false) | None => return Err(CommandError::TestModeOff { command }.into()),
+        }
+        log::warn!("Overwriting the {} calibration", point);
+        let reply = self.command(&command)?;
+        check_accepted(&command, &reply)?;
+        log::debug!("Set distance calibration");
+        Ok(())
+    }
+}
 
-        writeln!(self.serial_port, "getcharger\n")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripted_port::ScriptedPort;
 
-        let _s = match self.read_line() {
-            Ok(v) => log::debug!("{}", v),
-            Err(_) => log::error!("Error reading back"),
-        };
+    fn scan_reply(rotation_speed: &str) -> String {
+        format!(
+            "getldsscan\r\nAngleInDegrees,DistInMM,Intensity,ErrorCodeHEX\r\n0,1000,100,0\r\n\
+             ROTATION_SPEED,{}\r\n\x1a",
+            rotation_speed
+        )
+    }
 
-        self.serial_port.flush()?;
+    fn robot(port: &ScriptedPort) -> DSeries<'static> {
+        let mut robot = DSeries::new(Box::new(port.clone()));
+        robot.set_lds_spin_up(SpinUpConfig {
+            settle_count: 2,
+            poll_interval: time::Duration::from_millis(0),
+            timeout: time::Duration::from_secs(1),
+            ..Default::default()
+        });
+        robot
+    }
 
-        log::debug!("Serial port flushed");
+    #[test]
+    fn clean_leaves_unknown_test_mode_first() {
+        let port = ScriptedPort::new()
+            .expect("testmode off", "testmode off\r\n")
+            .expect("clean house", "clean house\r\n\x1a")
+            .expect("clean stop", "clean stop\r\n\x1a");
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        robot.clean(CleanMode::House).unwrap();
+        robot.clean(CleanMode::Stop).unwrap();
+        assert!(port.is_finished());
+    }
 
-        log::debug!("Reading values...");
-        loop {
-            let header = self.read_line()?;
-            log::debug!("{}", header);
+    #[test]
+    fn clean_reports_rejection() {
+        let port = ScriptedPort::new()
+            .expect("testmode off", "testmode off\r\n")
+            .expect("clean spot", "clean spot\r\nInvalid argument\r\n\x1a");
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        let err = robot.clean(CleanMode::Spot).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandError>(),
+            Some(CommandError::Rejected { .. })
+        ));
+    }
 
-            if header.contains("Label,Value") {
-                break;
-            }
+    #[test]
+    fn clean_refuses_in_test_mode() {
+        let port = ScriptedPort::new().expect("testmode on", "testmode on\r\n");
+        let mut robot = DSeries::new(Box::new(port.clone()));
+        robot.set_testmode(Toggle::On).unwrap();
+
+        let err = robot.clean(CleanMode::House).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandError>(),
+            Some(CommandError::TestModeOn { .. })
+        ));
+        assert_eq!(port.written(), vec!["testmode on"]);
+    }
+
+    #[test]
+    fn led_states_parse_back() {
+        for led in Led::ALL.iter() {
+            assert_eq!(led.to_string().parse::<Led>().unwrap(), *led);
         }
+    }
 
-        let lines = self.read_lines(16)?;
-        let status = ChargerStatus::from_str(&lines)?;
-        self.charger_status = status;
-        log::debug!("Got charger");
-        Ok(status)
+    #[test]
+    fn spin_up_waits_through_unreadable_scans() {
+        let port = ScriptedPort::new()
+            .expect("getldsscan", &scan_reply("1.5"))
+            .expect("getldsscan", &scan_reply("5.1"))
+            .expect("getldsscan", "getldsscan\r\n0,10#0,100,0\r\n\x1a")
+            .expect("getldsscan", &scan_reply("5.0"))
+            .expect("getldsscan", &scan_reply("4.9"));
+        let mut robot = robot(&port);
+
+        assert_eq!(robot.wait_for_lds_spin_up().unwrap(), 4.9);
+        assert!(port.is_finished());
     }
 
-    fn set_backlight(&mut self, value: Toggle) -> Result<()> {
-        writeln!(self.serial_port, "setled backlight{}", value.to_string())?;
-        Ok(())
+    #[test]
+    fn spin_up_fails_when_the_robot_does_not_answer() {
+        let mut robot = robot(&ScriptedPort::new());
+        let err = robot.wait_for_lds_spin_up().unwrap_err();
+        assert!(err.downcast_ref::<LdsError>().is_none(), "{:?}", err);
     }
 }
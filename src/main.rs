@@ -1,14 +1,41 @@
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::{thread, time};
-use std::time::Instant;
-use neato_driver::{DSeries, NeatoRobot, Toggle};
+
+use anyhow::{anyhow, Context, Result};
+use neato_driver::{
+    calibration::{self, DistanceCalPoint, WheelCalibrationConfig},
+    clock,
+    diagnostics::{self, LdsTestConfig},
+    guard::{self, RobotGuard},
+    recording::RecordingPort,
+    usage::{self, WearModel},
+    CleanMode, DSeries, LcdCommand, Led, NeatoRobot, SoundId, SystemMode, Toggle,
+};
+use serde::Serialize;
 use serialport::SerialPortSettings;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-fn main() {
-    let matches = App::new("Neato driver test application")
+mod cli;
+
+fn toggle_command<'a, 'b>(name: &'a str, about: &'a str) -> App<'a, 'b> {
+    SubCommand::with_name(name).about(about).arg(
+        Arg::with_name("state")
+            .help("Whether to turn it on or off")
+            .possible_values(&["on", "off"])
+            .required(true),
+    )
+}
+
+/// The command line, with `led_states` as the values the led subcommand takes.
+fn app<'a, 'b>(led_states: &'b [&'b str]) -> App<'a, 'b> {
+    App::new("Neato driver test application")
         .author("Loy van Beek <loy.vanbeek@mail.com>")
         .about("Controls a Neato vacum robot over it's serial port")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
         .arg(
             Arg::with_name("device")
                 .short("d")
@@ -24,18 +51,507 @@ fn main() {
                 .long("baudrate")
                 .default_value("115200"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("record")
+                .help("Record all serial communication to this file for later replay")
+                .short("r")
+                .long("record")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .help("Print results as JSON, one document per line")
+                .long("json")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("count")
+                .help("How often to poll, 0 polls until interrupted")
+                .short("n")
+                .long("count")
+                .default_value("1")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .help("Seconds between polls")
+                .short("i")
+                .long("interval")
+                .default_value("1")
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("Read LDS scans, turning on test mode and the LDS for the duration"),
+        )
+        .subcommand(
+            SubCommand::with_name("motors")
+                .about("Read or drive the motors")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("get").about("Read the motor status"))
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Drive the wheels in test mode")
+                        .setting(AppSettings::AllowNegativeNumbers)
+                        .arg(
+                            Arg::with_name("left")
+                                .help("Distance for the left wheel in mm")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("right")
+                                .help("Distance for the right wheel in mm")
+                                .required(true),
+                        )
+                        .arg(Arg::with_name("speed").help("Speed in mm/s").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sensors")
+                .about("Read the sensors")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("analog").about("Read the analog sensors"))
+                .subcommand(SubCommand::with_name("digital").about("Read the digital sensors")),
+        )
+        .subcommand(SubCommand::with_name("charger").about("Read the charger status"))
+        .subcommand(SubCommand::with_name("version").about("Read the firmware versions"))
+        .subcommand(
+            SubCommand::with_name("error")
+                .about("Read the error the firmware reports")
+                .arg(
+                    Arg::with_name("clear")
+                        .help("Dismiss the error after reading it")
+                        .long("clear"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("syslog").about("Read the system log"))
+        .subcommand(
+            SubCommand::with_name("usage")
+                .about("Read the lifetime counters and estimate brush and filter wear"),
+        )
+        .subcommand(
+            SubCommand::with_name("calibration")
+                .about("Read the calibration, recalibrate a distance sensor or the wheel distance")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("get").about("Read the calibration table"))
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Calibrate a drop or wall sensor with the target in place, in test mode")
+                        .arg(
+                            Arg::with_name("point")
+                                .help("Reading to take, as SetDistanceCal takes it")
+                                .possible_values(&[
+                                    "dropminimum",
+                                    "dropmiddle",
+                                    "dropmaximum",
+                                    "wallminimum",
+                                    "wallmiddle",
+                                    "wallmaximum",
+                                ])
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("yes")
+                                .help("Confirm overwriting the factory calibration")
+                                .long("yes"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("wheels")
+                        .about("Measure the wheel distance scale by driving towards a flat wall")
+                        .arg(
+                            Arg::with_name("distance")
+                                .help("Distance to drive in mm")
+                                .long("distance")
+                                .default_value("500"),
+                        )
+                        .arg(
+                            Arg::with_name("speed")
+                                .help("Speed in mm/s")
+                                .long("speed")
+                                .default_value("100"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("time")
+                .about("Read the robot clock or set it to the host time")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("get").about("Read the robot clock and its drift"))
+                .subcommand(
+                    SubCommand::with_name("sync").about("Set the robot clock to the host time"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ldstest")
+                .about("Check the health of the LDS, turning on test mode and the LDS for the duration")
+                .arg(
+                    Arg::with_name("scans")
+                        .help("Number of scans to analyze")
+                        .long("scans")
+                        .default_value("20"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("schedule")
+                .about("Read the cleaning schedule or turn it on or off")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("get").about("Read the cleaning schedule"))
+                .subcommand(toggle_command("set", "Enable or disable the cleaning schedule")),
+        )
+        .subcommand(toggle_command("testmode", "Turn test mode on or off"))
+        .subcommand(toggle_command("lds", "Turn the LDS rotation on or off"))
+        .subcommand(toggle_command(
+            "backlight",
+            "Turn the LCD backlight on or off",
+        ))
+        .subcommand(
+            SubCommand::with_name("led")
+                .about("Set an LED, in test mode")
+                .arg(
+                    Arg::with_name("state")
+                        .help("LED state as SetLED takes it")
+                        .possible_values(led_states)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lcd")
+                .about("Draw on the LCD, in test mode")
+                .arg(
+                    Arg::with_name("operation")
+                        .help("SetLCD operation, such as bgwhite, fgblack, hline 10 or contrast 40")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sound")
+                .about("Play a sound")
+                .arg(
+                    Arg::with_name("id")
+                        .help("Sound to play, from 0 to 20, or stop")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("clean")
+                .about("Start or stop a cleaning cycle, or check whether one is running")
+                .arg(
+                    Arg::with_name("mode")
+                        .help("What to do")
+                        .possible_values(&["house", "spot", "stop", "status"])
+                        .required(true),
+                ),
+        )
+        .subcommand(SubCommand::with_name("shutdown").about("Power down the robot"))
+        .subcommand(
+            SubCommand::with_name("power")
+                .about("Switch the power state, after stopping the motors and LDS")
+                .arg(
+                    Arg::with_name("mode")
+                        .help("Power state to switch to")
+                        .possible_values(&["shutdown", "hibernate", "standby", "powercycle"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("raw")
+                .about("Send any command and print its reply as a table")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("command")
+                        .help("Command with its arguments, such as getcalinfo")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("console")
+                .about("Type commands to the robot, with completion and history"),
+        )
+}
 
-    let port = matches.value_of("device").unwrap();
-    let baudrate: u32 = matches
-        .value_of("baudrate")
-        .unwrap_or("115200")
-        .parse::<u32>()
-        .unwrap();
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn print<T: Serialize + Debug>(&self, value: &T) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string(value)?);
+        } else {
+            println!("{:#?}", value);
+        }
+        Ok(())
+    }
+}
+
+struct Polling {
+    count: u64,
+    interval: time::Duration,
+    running: Arc<AtomicBool>,
+}
+
+impl Polling {
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Sleep, but wake up early when interrupted.
+    fn sleep(&self, duration: time::Duration) {
+        let start = time::Instant::now();
+        while self.is_running() && start.elapsed() < duration {
+            thread::sleep(duration.min(time::Duration::from_millis(50)));
+        }
+    }
+
+    fn run<F: FnMut() -> Result<()>>(&self, mut poll: F) -> Result<()> {
+        let mut polls = 0;
+        while self.is_running() && (self.count == 0 || polls < self.count) {
+            if polls > 0 {
+                self.sleep(self.interval);
+                if !self.is_running() {
+                    break;
+                }
+            }
+            poll()?;
+            polls += 1;
+        }
+        Ok(())
+    }
+}
+
+fn toggle(matches: &ArgMatches) -> Toggle {
+    match matches.value_of("state") {
+        Some("on") => Toggle::On,
+        _ => Toggle::Off,
+    }
+}
+
+fn parse<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = matches.value_of(name).unwrap_or_default();
+    value
+        .parse::<T>()
+        .with_context(|| format!("Invalid {}: {:?}", name, value))
+}
+
+fn parse_or_exit<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    parse(matches, name).unwrap_or_else(|err| {
+        eprintln!("{:?}", err);
+        std::process::exit(2);
+    })
+}
+
+/// Whether a subcommand leaves the robot in a state that should be restored afterwards.
+fn needs_exit(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        ("scan", _) | ("console", _) | ("lcd", _) => true,
+        ("motors", Some(motors)) => motors.subcommand_name() == Some("set"),
+        ("calibration", Some(calibration)) => calibration.subcommand_name() == Some("set"),
+        _ => false,
+    }
+}
+
+fn run(
+    robot: &mut DSeries,
+    matches: &ArgMatches,
+    polling: &Polling,
+    output: &Output,
+) -> Result<()> {
+    match matches.subcommand() {
+        ("scan", _) => {
+            robot.set_testmode(Toggle::On)?;
+            robot.set_ldsrotation(Toggle::On)?;
+            polling.run(|| {
+                robot.request_scan()?;
+                let ranges = robot.get_scan_ranges()?;
+                if output.json {
+                    output.print(&ranges)
+                } else {
+                    println!("{:?}", ranges);
+                    Ok(())
+                }
+            })
+        }
+        ("motors", Some(motors)) => match motors.subcommand() {
+            ("set", Some(set)) => {
+                let left: i32 = parse(set, "left")?;
+                let right: i32 = parse(set, "right")?;
+                let speed: i32 = parse(set, "speed")?;
+                robot.set_testmode(Toggle::On)?;
+                robot.set_motors(left, right, speed)?;
+                // Leaving test mode stops the wheels, so wait until they are done
+                if speed > 0 {
+                    let millis = left.abs().max(right.abs()) as u64 * 1000 / speed as u64;
+                    polling.sleep(time::Duration::from_millis(millis));
+                }
+                Ok(())
+            }
+            _ => polling.run(|| output.print(&robot.get_motors()?)),
+        },
+        ("sensors", Some(sensors)) => match sensors.subcommand_name() {
+            Some("analog") => polling.run(|| output.print(&robot.get_analog_sensors()?)),
+            _ => polling.run(|| output.print(&robot.get_digital_sensors()?)),
+        },
+        ("charger", _) => polling.run(|| output.print(&robot.get_charger()?)),
+        ("version", _) => output.print(&robot.get_version()?),
+        ("error", Some(error)) => {
+            match robot.get_error()? {
+                Some(robot_error) if !output.json => println!("{}", robot_error),
+                robot_error => output.print(&robot_error)?,
+            }
+            if error.is_present("clear") {
+                robot.clear_error()?;
+            }
+            Ok(())
+        }
+        ("syslog", _) => output.print(&robot.get_sys_log()?),
+        ("usage", _) => output.print(&usage::get_usage(robot, &WearModel::default())?),
+        ("calibration", Some(calibration)) => match calibration.subcommand() {
+            ("set", Some(set)) => {
+                let point: DistanceCalPoint = parse(set, "point")?;
+                if !set.is_present("yes") {
+                    return Err(anyhow!(
+                        "Setting {} overwrites the factory calibration, pass --yes to confirm",
+                        point
+                    ));
+                }
+                robot.set_testmode(Toggle::On)?;
+                robot.set_distance_cal(point)?;
+                output.print(&robot.get_cal_info()?)
+            }
+            ("wheels", Some(wheels)) => {
+                let config = WheelCalibrationConfig {
+                    distance_mm: parse(wheels, "distance")?,
+                    speed: parse(wheels, "speed")?,
+                    ..Default::default()
+                };
+                output.print(&calibration::calibrate_wheel_distance(robot, &config)?)
+            }
+            _ => output.print(&robot.get_cal_info()?),
+        },
+        ("time", Some(time)) => {
+            let drift = match time.subcommand_name() {
+                Some("sync") => clock::sync_clock(robot)?,
+                _ => {
+                    let now = robot.get_time()?;
+                    println!("{} {}", now.weekday, now.time);
+                    clock::measure_drift(robot)?
+                }
+            };
+            println!("Drift: {} s", drift.num_seconds());
+            Ok(())
+        }
+        ("ldstest", Some(ldstest)) => {
+            let config = LdsTestConfig {
+                scans: parse(ldstest, "scans")?,
+                ..Default::default()
+            };
+            let report = diagnostics::test_lds(robot, &config)?;
+            if output.json {
+                output.print(&report)?;
+            } else {
+                println!("Scans: {}", report.scans);
+                println!("Rotation speed: {:?}", report.rotation_speed);
+                println!("Intensity: {:?}", report.intensity);
+                println!("Invalid readings: {}", report.total_invalid_fraction);
+                println!("Stuck sectors: {:?}", report.stuck_sectors);
+                for failure in &report.failures {
+                    println!("Failed: {}", failure);
+                }
+            }
+            if report.passed() {
+                log::info!("LDS passed");
+                Ok(())
+            } else {
+                Err(anyhow!("LDS failed {} checks", report.failures.len()))
+            }
+        }
+        ("schedule", Some(schedule)) => match schedule.subcommand() {
+            ("set", Some(set)) => robot.set_schedule_enabled(toggle(set)),
+            _ => {
+                print!("{}", robot.get_schedule()?);
+                Ok(())
+            }
+        },
+        ("testmode", Some(testmode)) => robot.set_testmode(toggle(testmode)),
+        ("lds", Some(lds)) => robot.set_ldsrotation(toggle(lds)),
+        ("backlight", Some(backlight)) => robot.set_backlight(toggle(backlight)),
+        ("led", Some(led)) => {
+            let state = parse(led, "state")?;
+            // Stays in test mode, so the LED keeps its state
+            robot.set_testmode(Toggle::On)?;
+            robot.set_led(state)
+        }
+        ("lcd", Some(lcd)) => {
+            let operation: Vec<&str> = lcd.values_of("operation").unwrap_or_default().collect();
+            let command: LcdCommand = operation.join(" ").parse()?;
+            robot.set_testmode(Toggle::On)?;
+            robot.set_lcd(command)
+        }
+        ("sound", Some(sound)) => {
+            let id: SoundId = parse(sound, "id")?;
+            robot.play_sound(id)
+        }
+        ("clean", Some(clean)) => {
+            let mode = match clean.value_of("mode") {
+                Some("spot") => CleanMode::Spot,
+                Some("stop") => CleanMode::Stop,
+                Some("status") => return output.print(&robot.is_cleaning()?),
+                _ => CleanMode::House,
+            };
+            robot.clean(mode)
+        }
+        ("shutdown", _) => robot.set_system_mode(SystemMode::Shutdown),
+        ("power", Some(power)) => {
+            let mode = match power.value_of("mode") {
+                Some("hibernate") => SystemMode::Hibernate,
+                Some("standby") => SystemMode::Standby,
+                Some("powercycle") => SystemMode::PowerCycle,
+                _ => SystemMode::Shutdown,
+            };
+            robot.set_system_mode(mode)
+        }
+        ("raw", Some(raw)) => {
+            let command: Vec<&str> = raw.values_of("command").unwrap_or_default().collect();
+            output.print(&robot.raw_command(&command.join(" "))?)
+        }
+        ("console", _) => cli::console::run(robot, &polling.running),
+        _ => Ok(()),
+    }
+}
+
+fn main() {
+    let led_states: Vec<String> = Led::ALL.iter().map(Led::to_string).collect();
+    let led_states: Vec<&str> = led_states.iter().map(String::as_str).collect();
+    let matches = app(&led_states).get_matches();
 
     env_logger::init();
 
-    println!("Hello robot on port {}!", port);
+    let running = guard::shutdown_flag().expect("Failed to install signal handler");
+
+    let port = matches.value_of("device").unwrap();
+    let baudrate: u32 = parse_or_exit(&matches, "baudrate");
+    let interval: f64 = parse_or_exit(&matches, "interval");
+    let interval = time::Duration::try_from_secs_f64(interval).unwrap_or_else(|err| {
+        eprintln!("Invalid interval: {:?}: {}", interval, err);
+        std::process::exit(2);
+    });
+    let polling = Polling {
+        count: parse_or_exit(&matches, "count"),
+        interval,
+        running: running.clone(),
+    };
+    let output = Output {
+        json: matches.is_present("json"),
+    };
 
     let s = SerialPortSettings {
         baud_rate: baudrate,
@@ -44,66 +560,35 @@ fn main() {
         ..Default::default()
     };
 
-    println!("Opening serial port");
-    let comms = serialport::open_with_settings(port, &s).expect("Failed to open port");
-    println!("Opened serial port");
-
-    println!("Creating robot");
-    let mut robot = DSeries::new(comms);
-    println!("Create robot");
-
-    robot
-        .set_testmode(Toggle::On)
-        .expect("Failed to enable testmode");
-    thread::sleep(time::Duration::from_millis(500));
+    log::info!("Opening serial port {}", port);
+    let mut comms = serialport::open_with_settings(port, &s).expect("Failed to open port");
 
-    robot.set_backlight(Toggle::Off).unwrap();
+    if let Some(path) = matches.value_of("record") {
+        log::info!("Recording serial communication to {}", path);
+        comms = Box::new(
+            RecordingPort::create(comms, Path::new(path)).expect("Failed to create recording"),
+        );
+    }
 
-    robot
-        .set_ldsrotation(Toggle::On)
-        .expect("Failed to enable LDS rotation");
+    // Restores the robot if run panics
+    let mut robot = RobotGuard::new(DSeries::new(comms));
 
-    loop {
-        let start = Instant::now();
-        robot.request_scan().expect("Failed to request a scan");
-        // thread::sleep(time::Duration::from_millis(500));
+    let result = run(&mut robot, &matches, &polling, &output);
 
-        match robot.get_scan_ranges() {
-            Ok(ranges) => println!("{:?}", ranges),
-            Err(err) => {
-                eprintln!("Could not get_scan_ranges: {:?}", err);
-                // robot.exit().expect("Failed to exit robot while handling err");
-            }
+    let interrupted = !running.load(Ordering::SeqCst);
+    if interrupted || needs_exit(&matches) {
+        if let Err(err) = robot.release() {
+            eprintln!("Failed to restore robot: {:?}", err);
         }
-        // thread::sleep(time::Duration::from_secs(5));
-
-        // robot.set_motors(20, 20, 10).expect("Could not set motors");
-        let motor_status = robot.get_motors().expect("Could not get motor data");
-        println!("{:?}", motor_status);
-
-        // let analog_status = robot
-        //     .get_analog_sensors()
-        //     .expect("Could not get analog sensors");
-        // println!("{:?}", analog_status);
-        //
-        // let digital_status = robot
-        //     .get_digital_sensors()
-        //     .expect("Could not get digital sensors");
-        // println!("{:?}", digital_status);
-        //
-        // let charger_status = robot.get_charger().expect("Could not get charger status");
-        // println!("{:?}", charger_status);
-
-        println!("{}", robot);
-
-        // thread::sleep(time::Duration::from_secs(5));
-        // robot
-        //     .set_motors(-20, -20, 10)
-        //     .expect("Could not set motors");
-
-        // robot.set_backlight(Toggle::On).unwrap();
-
-        println!("Loop duration: {:?}", start.elapsed());
+    } else {
+        robot.disarm();
+    }
+
+    if let Err(err) = result {
+        eprintln!("{:?}", err);
+        std::process::exit(1);
+    }
+    if interrupted {
+        std::process::exit(130);
     }
-    robot.exit().expect("Failed to exit robot");
 }
@@ -0,0 +1,437 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::{
+    geometry::{Point2, Pose2D},
+    lds::ScanProjector,
+    odometry::WheelOdometry,
+    MotorStatus,
+};
+
+/// Probability thresholds written to the map metadata, as used by ROS map_server.
+const OCCUPIED_THRESHOLD: f32 = 0.65;
+const FREE_THRESHOLD: f32 = 0.196;
+
+/// Pixel values of the exported image, following the map_server trinary convention.
+const OCCUPIED_PIXEL: u8 = 0;
+const FREE_PIXEL: u8 = 254;
+const UNKNOWN_PIXEL: u8 = 205;
+
+fn log_odds(probability: f32) -> f32 {
+    (probability / (1.0 - probability)).ln()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LogOddsModel {
+    /// Added to a cell that a beam ended in.
+    pub hit: f32,
+    /// Added to every cell a beam passed through.
+    pub miss: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for LogOddsModel {
+    fn default() -> Self {
+        Self {
+            hit: log_odds(0.7),
+            miss: log_odds(0.4),
+            min: -5.0,
+            max: 5.0,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MapError {
+    #[error("Map resolution must be a positive number of meters, not {0}")]
+    InvalidResolution(f32),
+    #[error("Map of {width} by {height} meters has no cells")]
+    InvalidSize { width: f32, height: f32 },
+    #[error("Map image {0:?} must end in .pgm or .png")]
+    UnsupportedImage(PathBuf),
+}
+
+/// A fixed-size 2D log-odds occupancy grid.
+///
+/// Cell (0, 0) is the bottom-left cell, its corner is at `origin` in the map frame.
+#[derive(Debug, Clone)]
+pub struct OccupancyGrid {
+    resolution: f32,
+    width: usize,
+    height: usize,
+    origin: Point2,
+    model: LogOddsModel,
+    cells: Vec<f32>,
+}
+
+impl OccupancyGrid {
+    /// A grid of `width` by `height` meters centered on the map origin.
+    pub fn new(width: f32, height: f32, resolution: f32) -> Result<Self, MapError> {
+        if !(resolution.is_finite() && resolution > 0.0) {
+            return Err(MapError::InvalidResolution(resolution));
+        }
+        let columns = (width / resolution).ceil();
+        let rows = (height / resolution).ceil();
+        if !(columns.is_finite() && rows.is_finite() && columns >= 1.0 && rows >= 1.0) {
+            return Err(MapError::InvalidSize { width, height });
+        }
+        let (columns, rows) = (columns as usize, rows as usize);
+        Ok(Self {
+            resolution,
+            width: columns,
+            height: rows,
+            origin: Point2::new(
+                -(columns as f32) * resolution / 2.0,
+                -(rows as f32) * resolution / 2.0,
+            ),
+            model: LogOddsModel::default(),
+            cells: vec![0.0; columns * rows],
+        })
+    }
+
+    pub fn with_model(mut self, model: LogOddsModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn origin(&self) -> Point2 {
+        self.origin
+    }
+
+    pub fn cell(&self, point: &Point2) -> Option<(usize, usize)> {
+        let column = ((point.x - self.origin.x) / self.resolution).floor();
+        let row = ((point.y - self.origin.y) / self.resolution).floor();
+        if column < 0.0 || row < 0.0 {
+            return None;
+        }
+        let (column, row) = (column as usize, row as usize);
+        if column < self.width && row < self.height {
+            Some((column, row))
+        } else {
+            None
+        }
+    }
+
+    /// Occupancy probability of a cell, `None` if it was never observed or is
+    /// outside the grid.
+    pub fn probability(&self, column: usize, row: usize) -> Option<f32> {
+        if column >= self.width || row >= self.height {
+            return None;
+        }
+        let value = self.cells[row * self.width + column];
+        if value == 0.0 {
+            None
+        } else {
+            Some(1.0 - 1.0 / (1.0 + value.exp()))
+        }
+    }
+
+    /// Trace beams from `sensor` to each of `hits`, all in the map frame.
+    pub fn insert_rays(&mut self, sensor: &Point2, hits: &[Point2]) {
+        let start = match self.cell(sensor) {
+            Some(cell) => cell,
+            None => {
+                log::error!("Sensor at {:?} is outside the map", sensor);
+                return;
+            }
+        };
+
+        for hit in hits {
+            let end = match self.cell(hit) {
+                Some(cell) => cell,
+                None => continue,
+            };
+            for (column, row) in Line::new(start, end) {
+                self.add(column, row, self.model.miss);
+            }
+            self.add(end.0, end.1, self.model.hit);
+        }
+    }
+
+    fn add(&mut self, column: usize, row: usize, value: f32) {
+        let cell = &mut self.cells[row * self.width + column];
+        *cell = (*cell + value).max(self.model.min).min(self.model.max);
+    }
+
+    fn pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        // Images start at the top row
+        for row in (0..self.height).rev() {
+            for column in 0..self.width {
+                pixels.push(match self.probability(column, row) {
+                    Some(p) if p > OCCUPIED_THRESHOLD => OCCUPIED_PIXEL,
+                    Some(p) if p < FREE_THRESHOLD => FREE_PIXEL,
+                    _ => UNKNOWN_PIXEL,
+                });
+            }
+        }
+        pixels
+    }
+
+    pub fn write_pgm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P5\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.pixels())
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels())?;
+        Ok(())
+    }
+
+    /// Write the map_server metadata for an image written next to it.
+    pub fn write_yaml<W: Write>(&self, mut writer: W, image: &str) -> io::Result<()> {
+        writeln!(writer, "image: {}", image)?;
+        writeln!(writer, "resolution: {:.6}", self.resolution)?;
+        writeln!(
+            writer,
+            "origin: [{:.6}, {:.6}, 0.000000]",
+            self.origin.x, self.origin.y
+        )?;
+        writeln!(writer, "negate: 0")?;
+        writeln!(writer, "occupied_thresh: {}", OCCUPIED_THRESHOLD)?;
+        writeln!(writer, "free_thresh: {}", FREE_THRESHOLD)
+    }
+
+    /// Save `<path>.pgm` or `<path>.png`, depending on its extension, and `<path>.yaml`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if !matches!(extension, Some("pgm") | Some("png")) {
+            return Err(MapError::UnsupportedImage(path.to_path_buf()).into());
+        }
+        let image = File::create(path).with_context(|| format!("Could not create {:?}", path))?;
+        match extension {
+            Some("png") => self.write_png(BufWriter::new(image))?,
+            _ => self.write_pgm(BufWriter::new(image))?,
+        }
+
+        let yaml = path.with_extension("yaml");
+        let image_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Map path has no file name")?;
+        let file = File::create(&yaml).with_context(|| format!("Could not create {:?}", yaml))?;
+        self.write_yaml(BufWriter::new(file), image_name)?;
+        Ok(())
+    }
+}
+
+/// Cells on the line between two cells, excluding the last one.
+struct Line {
+    column: i64,
+    row: i64,
+    end: (i64, i64),
+    step: (i64, i64),
+    delta: (i64, i64),
+    error: i64,
+}
+
+impl Line {
+    fn new(start: (usize, usize), end: (usize, usize)) -> Self {
+        let (column, row) = (start.0 as i64, start.1 as i64);
+        let end = (end.0 as i64, end.1 as i64);
+        let delta = ((end.0 - column).abs(), -(end.1 - row).abs());
+        Self {
+            column,
+            row,
+            end,
+            step: ((end.0 - column).signum(), (end.1 - row).signum()),
+            delta,
+            error: delta.0 + delta.1,
+        }
+    }
+}
+
+impl Iterator for Line {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if (self.column, self.row) == self.end {
+            return None;
+        }
+        let cell = (self.column as usize, self.row as usize);
+        let doubled = 2 * self.error;
+        if doubled >= self.delta.1 {
+            self.error += self.delta.1;
+            self.column += self.step.0;
+        }
+        if doubled <= self.delta.0 {
+            self.error += self.delta.0;
+            self.row += self.step.1;
+        }
+        Some(cell)
+    }
+}
+
+/// Builds an occupancy grid from LDS scans placed with wheel odometry.
+pub struct Mapper {
+    pub grid: OccupancyGrid,
+    pub projector: ScanProjector,
+    odometry: WheelOdometry,
+}
+
+impl Mapper {
+    pub fn new(grid: OccupancyGrid, projector: ScanProjector) -> Self {
+        Self {
+            grid,
+            projector,
+            odometry: WheelOdometry::default(),
+        }
+    }
+
+    pub fn pose(&self) -> Pose2D {
+        self.odometry.pose()
+    }
+
+    /// Add a scan taken at the wheel positions in `motors`.
+    pub fn update(&mut self, motors: &MotorStatus, ranges: &[f32]) {
+        self.odometry.update(motors);
+        self.insert_scan(&self.odometry.pose(), ranges);
+    }
+
+    /// Add a scan taken at `pose`, for callers with a better pose estimate than odometry.
+    pub fn insert_scan(&mut self, pose: &Pose2D, ranges: &[f32]) {
+        let sensor = pose.transform(&Point2::new(self.projector.mount.x, self.projector.mount.y));
+        let hits: Vec<Point2> = self
+            .projector
+            .project(ranges)
+            .iter()
+            .map(|point| pose.transform(point))
+            .collect();
+        self.grid.insert_rays(&sensor, &hits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_marks_hit_occupied_and_ray_free() {
+        // 2 by 2 meters in 10 cm cells, so the robot is in cell (10, 10)
+        let mut mapper = Mapper::new(
+            OccupancyGrid::new(2.0, 2.0, 0.1).unwrap(),
+            ScanProjector::default(),
+        );
+        let mut ranges = vec![0.0; 360];
+        ranges[0] = 0.55;
+        mapper.insert_scan(&Pose2D::default(), &ranges);
+
+        let grid = &mapper.grid;
+        assert_eq!(grid.cell(&Point2::new(0.55, 0.0)), Some((15, 10)));
+        assert!(grid.probability(15, 10).unwrap() > OCCUPIED_THRESHOLD);
+        for column in 10..15 {
+            assert!(grid.probability(column, 10).unwrap() < 0.5);
+        }
+        assert_eq!(grid.probability(16, 10), None);
+        assert_eq!(grid.probability(10, 11), None);
+    }
+
+    #[test]
+    fn repeated_hits_saturate() {
+        let model = LogOddsModel::default();
+        let mut grid = OccupancyGrid::new(1.0, 1.0, 0.1).unwrap();
+        for _ in 0..100 {
+            grid.insert_rays(&Point2::new(0.0, 0.0), &[Point2::new(0.3, 0.0)]);
+        }
+        let (column, row) = grid.cell(&Point2::new(0.3, 0.0)).unwrap();
+        let saturated = 1.0 - 1.0 / (1.0 + model.max.exp());
+        assert!((grid.probability(column, row).unwrap() - saturated).abs() < 1e-6);
+    }
+
+    #[test]
+    fn probability_outside_grid_is_none() {
+        let grid = OccupancyGrid::new(1.0, 1.0, 0.1).unwrap();
+        assert_eq!(grid.probability(10, 0), None);
+        assert_eq!(grid.probability(0, 10), None);
+    }
+
+    #[test]
+    fn rejects_grids_without_cells() {
+        for resolution in [0.0, -0.1, f32::NAN, f32::INFINITY].iter() {
+            assert!(matches!(
+                OccupancyGrid::new(1.0, 1.0, *resolution),
+                Err(MapError::InvalidResolution(_))
+            ));
+        }
+        assert!(matches!(
+            OccupancyGrid::new(0.0, 1.0, 0.1),
+            Err(MapError::InvalidSize { .. })
+        ));
+        assert!(matches!(
+            OccupancyGrid::new(1.0, -1.0, 0.1),
+            Err(MapError::InvalidSize { .. })
+        ));
+        assert!(matches!(
+            OccupancyGrid::new(f32::INFINITY, 1.0, 0.1),
+            Err(MapError::InvalidSize { .. })
+        ));
+    }
+
+    #[test]
+    fn save_refuses_other_image_formats() {
+        let grid = OccupancyGrid::new(1.0, 1.0, 0.1).unwrap();
+        let path = std::env::temp_dir().join("neato_map_test.yaml");
+        let err = grid.save(&path).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<MapError>(),
+            Some(&MapError::UnsupportedImage(path.clone()))
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn writes_map_server_yaml() {
+        let grid = OccupancyGrid::new(2.0, 1.0, 0.05).unwrap();
+        let mut yaml = vec![];
+        grid.write_yaml(&mut yaml, "map.pgm").unwrap();
+        assert_eq!(
+            String::from_utf8(yaml).unwrap(),
+            "image: map.pgm\n\
+             resolution: 0.050000\n\
+             origin: [-1.000000, -0.500000, 0.000000]\n\
+             negate: 0\n\
+             occupied_thresh: 0.65\n\
+             free_thresh: 0.196\n"
+        );
+    }
+
+    #[test]
+    fn writes_pgm_top_row_first() {
+        let mut grid = OccupancyGrid::new(0.2, 0.2, 0.1).unwrap();
+        // Enough beams to push both cells past the thresholds
+        for _ in 0..5 {
+            grid.insert_rays(&Point2::new(-0.05, -0.05), &[Point2::new(-0.05, 0.05)]);
+        }
+        let mut pgm = vec![];
+        grid.write_pgm(&mut pgm).unwrap();
+        let header = b"P5\n2 2\n255\n";
+        assert_eq!(&pgm[..header.len()], header);
+        assert_eq!(
+            &pgm[header.len()..],
+            &[OCCUPIED_PIXEL, UNKNOWN_PIXEL, FREE_PIXEL, UNKNOWN_PIXEL]
+        );
+    }
+}
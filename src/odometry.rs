@@ -0,0 +1,144 @@
+use crate::{geometry::Pose2D, MotorStatus};
+
+/// Distance between the wheels of a D-series robot, in meters.
+pub const WHEEL_BASE: f32 = 0.248;
+
+/// Dead reckoning from the wheel encoder positions reported by `get_motors`.
+#[derive(Debug, Clone, Copy)]
+pub struct WheelOdometry {
+    wheel_base: f32,
+    distance_scale: f32,
+    last_positions: Option<(i32, i32)>,
+    pose: Pose2D,
+}
+
+impl Default for WheelOdometry {
+    fn default() -> Self {
+        Self::new(WHEEL_BASE)
+    }
+}
+
+impl WheelOdometry {
+    pub fn new(wheel_base: f32) -> Self {
+        Self {
+            wheel_base,
+            distance_scale: 1.0,
+            last_positions: None,
+            pose: Pose2D::default(),
+        }
+    }
+
+    pub fn pose(&self) -> Pose2D {
+        self.pose
+    }
+
+    /// Correct the distances the wheels report, see
+    /// `calibration::calibrate_wheel_distance`.
+    pub fn set_distance_scale(&mut self, scale: f32) {
+        self.distance_scale = scale;
+    }
+
+    pub fn reset(&mut self, pose: Pose2D) {
+        self.pose = pose;
+    }
+
+    /// Integrate a new motor status and return the motion since the previous one,
+    /// relative to the previous pose.
+    pub fn update(&mut self, status: &MotorStatus) -> Pose2D {
+        self.update_positions(
+            status.left_wheel_position_in_mm,
+            status.right_wheel_position_in_mm,
+        )
+    }
+
+    pub fn update_positions(&mut self, left_mm: i32, right_mm: i32) -> Pose2D {
+        let delta = match self.last_positions {
+            Some((last_left, last_right)) => {
+                let scale = self.distance_scale / 1000.0; // millimeters to meters
+                let left = (left_mm - last_left) as f32 * scale;
+                let right = (right_mm - last_right) as f32 * scale;
+                let distance = (left + right) / 2.0;
+                let rotation = (right - left) / self.wheel_base;
+                // Drive along the mean heading of this step
+                let (sin, cos) = (rotation / 2.0).sin_cos();
+                Pose2D::new(distance * cos, distance * sin, rotation)
+            }
+            None => Pose2D::default(),
+        };
+        self.last_positions = Some((left_mm, right_mm));
+        self.pose = self.pose.compose(&delta);
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_pose_near(pose: &Pose2D, x: f32, y: f32, theta: f32) {
+        assert!(
+            (pose.x - x).abs() < EPSILON
+                && (pose.y - y).abs() < EPSILON
+                && (pose.theta - theta).abs() < EPSILON,
+            "{:?} is not ({}, {}, {})",
+            pose,
+            x,
+            y,
+            theta
+        );
+    }
+
+    #[test]
+    fn first_update_does_not_move() {
+        let mut odometry = WheelOdometry::default();
+        assert_eq!(odometry.update_positions(1200, -300), Pose2D::default());
+        assert_eq!(odometry.pose(), Pose2D::default());
+    }
+
+    #[test]
+    fn integrates_drive_turn_drive() {
+        let mut odometry = WheelOdometry::new(0.2);
+        odometry.update_positions(0, 0);
+
+        let delta = odometry.update_positions(100, 100);
+        assert_pose_near(&delta, 0.1, 0.0, 0.0);
+
+        // Spinning in place by 0.314 / 0.2 = 1.57 radians
+        let delta = odometry.update_positions(-57, 257);
+        assert_pose_near(&delta, 0.0, 0.0, 1.57);
+
+        odometry.update_positions(43, 357);
+        let (sin, cos) = 1.57f32.sin_cos();
+        assert_pose_near(&odometry.pose(), 0.1 + 0.1 * cos, 0.1 * sin, 1.57);
+    }
+
+    #[test]
+    fn drives_along_the_mean_heading_of_an_arc() {
+        let mut odometry = WheelOdometry::new(0.2);
+        odometry.update_positions(0, 0);
+        let delta = odometry.update_positions(100, 200);
+        let rotation = 0.1 / 0.2;
+        let (sin, cos) = (rotation / 2.0f32).sin_cos();
+        assert_pose_near(&delta, 0.15 * cos, 0.15 * sin, rotation);
+        assert_eq!(odometry.pose(), delta);
+    }
+
+    #[test]
+    fn scales_wheel_distances() {
+        let mut odometry = WheelOdometry::default();
+        odometry.set_distance_scale(0.9);
+        odometry.update_positions(0, 0);
+        assert_pose_near(&odometry.update_positions(1000, 1000), 0.9, 0.0, 0.0);
+    }
+
+    #[test]
+    fn reset_keeps_the_encoder_reference() {
+        let mut odometry = WheelOdometry::default();
+        odometry.update_positions(500, 500);
+        odometry.reset(Pose2D::new(1.0, 1.0, 0.0));
+        odometry.update_positions(600, 600);
+        assert_pose_near(&odometry.pose(), 1.1, 1.0, 0.0);
+    }
+}
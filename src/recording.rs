@@ -0,0 +1,423 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serialport::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits,
+};
+
+#[cfg(any(test, feature = "testing"))]
+use crate::scripted_port::ScriptedPort;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes the robot sent to us.
+    Read,
+    /// Bytes we sent to the robot.
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    /// Time since the recording started.
+    pub elapsed: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+impl RecordedEvent {
+    /// One line of `<microseconds> <R|W> <hex bytes>`.
+    fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let direction = match self.direction {
+            Direction::Read => 'R',
+            Direction::Write => 'W',
+        };
+        write!(writer, "{} {} ", self.elapsed.as_micros(), direction)?;
+        for byte in &self.data {
+            write!(writer, "{:02x}", byte)?;
+        }
+        writeln!(writer)
+    }
+
+    fn parse(line: &str) -> io::Result<Self> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {} in recording line {:?}", what, line),
+            )
+        };
+
+        let mut fields = line.split_whitespace();
+        let elapsed = fields
+            .next()
+            .and_then(|micros| micros.parse::<u64>().ok())
+            .ok_or_else(|| invalid("timestamp"))?;
+        let direction = match fields.next() {
+            Some("R") => Direction::Read,
+            Some("W") => Direction::Write,
+            _ => return Err(invalid("direction")),
+        };
+        let hex = fields.next().unwrap_or("");
+        let data = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .filter(|pair| pair.len() == 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| invalid("data"))
+            })
+            .collect::<io::Result<Vec<u8>>>()?;
+
+        Ok(Self {
+            elapsed: Duration::from_micros(elapsed),
+            direction,
+            data,
+        })
+    }
+}
+
+/// The recording of a `RecordingPort` and its clones.
+struct Log {
+    writer: Box<dyn Write + Send>,
+    /// Bytes in the current direction, not yet written, since `DSeries` reads byte
+    /// by byte and writes a command in pieces.
+    pending: Option<RecordedEvent>,
+}
+
+impl Log {
+    fn record(&mut self, elapsed: Duration, direction: Direction, data: &[u8]) -> io::Result<()> {
+        match &mut self.pending {
+            Some(pending) if pending.direction == direction => {
+                pending.data.extend(data);
+                Ok(())
+            }
+            _ => {
+                let written = self.write_pending();
+                self.pending = Some(RecordedEvent {
+                    elapsed,
+                    direction,
+                    data: data.to_vec(),
+                });
+                written
+            }
+        }
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if let Some(event) = self.pending.take() {
+            event.write_to(&mut *self.writer)?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        if let Err(err) = self.write_pending() {
+            log::error!("Could not record serial data: {:?}", err);
+        }
+    }
+}
+
+/// Wraps a serial port and logs every byte read and written, with timestamps.
+///
+/// Consecutive reads, or writes, are logged as one entry with the time of the first.
+/// Pass it to `DSeries::new` in place of the real port. Clones share the same log,
+/// which is completed when the last of them is dropped.
+pub struct RecordingPort {
+    port: Box<dyn SerialPort>,
+    log: Arc<Mutex<Log>>,
+    start: Instant,
+}
+
+impl RecordingPort {
+    pub fn new(port: Box<dyn SerialPort>, log: Box<dyn Write + Send>) -> Self {
+        Self {
+            port,
+            log: Arc::new(Mutex::new(Log {
+                writer: log,
+                pending: None,
+            })),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn create(port: Box<dyn SerialPort>, path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(port, Box::new(BufWriter::new(file))))
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        if let Err(err) = log.record(self.start.elapsed(), direction, data) {
+            log::error!("Could not record serial data: {:?}", err);
+        }
+    }
+}
+
+impl io::Read for RecordingPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.port.read(buf)?;
+        self.record(Direction::Read, &buf[..count]);
+        Ok(count)
+    }
+}
+
+impl io::Write for RecordingPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.port.write(buf)?;
+        self.record(Direction::Write, &buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl SerialPort for RecordingPort {
+    fn name(&self) -> Option<String> {
+        self.port.name()
+    }
+
+    fn settings(&self) -> SerialPortSettings {
+        self.port.settings()
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        self.port.baud_rate()
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        self.port.data_bits()
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        self.port.flow_control()
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        self.port.parity()
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        self.port.stop_bits()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.port.set_all(settings)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.port.set_baud_rate(baud_rate)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.port.set_data_bits(data_bits)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.port.set_flow_control(flow_control)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.port.set_parity(parity)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.port.set_stop_bits(stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.port.set_timeout(timeout)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_request_to_send(level)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_data_terminal_ready(level)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.port.read_clear_to_send()
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.port.read_data_set_ready()
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.port.read_ring_indicator()
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.port.read_carrier_detect()
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_read()
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_write()
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        self.port.clear(buffer_to_clear)
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(Self {
+            port: self.port.try_clone()?,
+            log: self.log.clone(),
+            start: self.start,
+        }))
+    }
+}
+
+/// A serial session captured by `RecordingPort`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut events = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                events.push(RecordedEvent::parse(&line)?);
+            }
+        }
+        Ok(Self { events })
+    }
+
+    /// A port that expects the recorded commands and answers each with the bytes
+    /// the robot sent after it, so a `DSeries` session replays without hardware.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn replay(&self) -> ScriptedPort {
+        let port = ScriptedPort::new();
+        let mut exchanges: Vec<(String, Vec<u8>)> = vec![];
+        let mut partial_line = vec![];
+
+        for event in &self.events {
+            match event.direction {
+                Direction::Write => {
+                    for byte in &event.data {
+                        if *byte == b'\n' {
+                            let command = String::from_utf8_lossy(&partial_line).trim().to_string();
+                            partial_line.clear();
+                            if !command.is_empty() {
+                                exchanges.push((command, vec![]));
+                            }
+                        } else {
+                            partial_line.push(*byte);
+                        }
+                    }
+                }
+                Direction::Read => match exchanges.last_mut() {
+                    Some((_command, reply)) => reply.extend(&event.data),
+                    None => port.push_reply(&event.data),
+                },
+            }
+        }
+
+        exchanges.iter().fold(port, |port, (command, reply)| {
+            port.expect_bytes(command, reply)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DSeries, NeatoRobot};
+
+    const MOTORS: &str = "getmotors\r\nParameter,Value\r\nBrush_RPM,0\r\nBrush_mA,0\r\n\
+        Vacuum_RPM,0\r\nVacuum_mA,0\r\nLeftWheel_RPM,0\r\nLeftWheel_Load%,0\r\n\
+        LeftWheel_PositionInMM,1234\r\nLeftWheel_Speed,0\r\nRightWheel_RPM,0\r\n\
+        RightWheel_Load%,0\r\nRightWheel_PositionInMM,-56\r\nRightWheel_Speed,0\r\n\
+        SideBrush_mA,0\r\n\x1a";
+
+    /// A log that can be read back after the port that writes it is dropped.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record_motors() -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let port = ScriptedPort::new().expect("getmotors", MOTORS);
+        {
+            let recording = RecordingPort::new(Box::new(port), Box::new(buffer.clone()));
+            let mut robot = DSeries::new(Box::new(recording));
+            robot.get_motors().unwrap();
+        }
+        let log = buffer.0.lock().unwrap().clone();
+        log
+    }
+
+    #[test]
+    fn coalesces_reads_and_writes() {
+        let recording = Recording::read(&record_motors()[..]).unwrap();
+        let directions: Vec<Direction> = recording
+            .events
+            .iter()
+            .map(|event| event.direction)
+            .collect();
+        assert_eq!(directions, vec![Direction::Write, Direction::Read]);
+        assert_eq!(recording.events[0].data, b"getmotors\n");
+        assert_eq!(recording.events[1].data, MOTORS.as_bytes());
+    }
+
+    #[test]
+    fn replays_recorded_session() {
+        let original = DSeries::new(Box::new(ScriptedPort::new().expect("getmotors", MOTORS)))
+            .get_motors()
+            .unwrap();
+
+        let recording = Recording::read(&record_motors()[..]).unwrap();
+        let port = recording.replay();
+        let mut robot = DSeries::new(Box::new(port.clone()));
+        let replayed = robot.get_motors().unwrap();
+
+        assert_eq!(replayed, original);
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(Recording::read(&b"12 X 00\n"[..]).is_err());
+        assert!(Recording::read(&b"12 R 0g\n"[..]).is_err());
+        assert!(Recording::read(&b"R 00\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,554 @@
+use crate::{
+    geometry::{normalize_angle, Point2, Pose2D},
+    lds::ScanProjector,
+    odometry::WheelOdometry,
+    MotorStatus,
+};
+
+/// Covariance of (x, y, theta).
+pub type Covariance = [[f32; 3]; 3];
+
+#[derive(Debug, Clone, Copy)]
+pub struct IcpConfig {
+    pub max_iterations: usize,
+    /// Point pairs further apart than this, in meters, are not used.
+    pub max_correspondence_distance: f32,
+    /// Stop when an iteration moves the estimate less than this, in meters and radians.
+    pub convergence_threshold: f32,
+    /// Give up when fewer point pairs than this are found.
+    pub min_correspondences: usize,
+}
+
+impl Default for IcpConfig {
+    fn default() -> Self {
+        Self {
+            max_iterations: 30,
+            max_correspondence_distance: 0.3,
+            convergence_threshold: 1e-4,
+            min_correspondences: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScanMatch {
+    /// Pose of the current scan in the frame of the reference scan.
+    pub pose: Pose2D,
+    pub covariance: Covariance,
+    /// Root mean square distance between matched points, in meters.
+    pub error: f32,
+    pub correspondences: usize,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+/// Point-to-point ICP between two scans.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanMatcher {
+    pub config: IcpConfig,
+}
+
+impl ScanMatcher {
+    pub fn new(config: IcpConfig) -> Self {
+        Self { config }
+    }
+
+    /// Align `current` onto `reference`, starting from `initial`, typically the odometry delta.
+    ///
+    /// Returns `None` when the scans do not overlap enough to be matched.
+    /// Points that are not finite, such as those of invalid readings, are ignored.
+    pub fn match_scans(
+        &self,
+        reference: &[Point2],
+        current: &[Point2],
+        initial: &Pose2D,
+    ) -> Option<ScanMatch> {
+        let reference = finite(reference);
+        let current = finite(current);
+        let (reference, current) = (&reference[..], &current[..]);
+        let mut estimate = *initial;
+        let mut converged = false;
+        let mut iterations = 0;
+
+        while iterations < self.config.max_iterations {
+            iterations += 1;
+            let pairs = self.correspondences(reference, current, &estimate);
+            if pairs.len() < self.config.min_correspondences {
+                log::debug!("Only {} correspondences, giving up", pairs.len());
+                return None;
+            }
+
+            let step = align(&pairs);
+            estimate = step.compose(&estimate);
+            if step.x.hypot(step.y) < self.config.convergence_threshold
+                && step.theta.abs() < self.config.convergence_threshold
+            {
+                converged = true;
+                break;
+            }
+        }
+
+        let pairs = self.correspondences(reference, current, &estimate);
+        if pairs.len() < self.config.min_correspondences {
+            return None;
+        }
+        let squared_error: f32 = pairs
+            .iter()
+            .map(|(p, q)| (p.x - q.x).powi(2) + (p.y - q.y).powi(2))
+            .sum();
+        let covariance = covariance(&pairs, &estimate, squared_error)?;
+
+        Some(ScanMatch {
+            pose: estimate,
+            covariance,
+            error: (squared_error / pairs.len() as f32).sqrt(),
+            correspondences: pairs.len(),
+            iterations,
+            converged,
+        })
+    }
+
+    /// Pairs of (transformed current point, nearest reference point).
+    fn correspondences(
+        &self,
+        reference: &[Point2],
+        current: &[Point2],
+        estimate: &Pose2D,
+    ) -> Vec<(Point2, Point2)> {
+        let max_distance = self.config.max_correspondence_distance;
+        current
+            .iter()
+            .filter_map(|point| {
+                let moved = estimate.transform(point);
+                reference
+                    .iter()
+                    .map(|candidate| (moved.distance(candidate), candidate))
+                    .filter(|(distance, _)| *distance <= max_distance)
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .map(|(_, nearest)| (moved, *nearest))
+            })
+            .collect()
+    }
+}
+
+fn finite(points: &[Point2]) -> Vec<Point2> {
+    points
+        .iter()
+        .filter(|point| point.x.is_finite() && point.y.is_finite())
+        .copied()
+        .collect()
+}
+
+/// The rigid transform that best moves the first point of each pair onto the second.
+fn align(pairs: &[(Point2, Point2)]) -> Pose2D {
+    let count = pairs.len() as f32;
+    let (mut p_mean, mut q_mean) = (Point2::default(), Point2::default());
+    for (p, q) in pairs {
+        p_mean.x += p.x / count;
+        p_mean.y += p.y / count;
+        q_mean.x += q.x / count;
+        q_mean.y += q.y / count;
+    }
+
+    let (mut dot, mut cross) = (0.0, 0.0);
+    for (p, q) in pairs {
+        let (px, py) = (p.x - p_mean.x, p.y - p_mean.y);
+        let (qx, qy) = (q.x - q_mean.x, q.y - q_mean.y);
+        dot += px * qx + py * qy;
+        cross += px * qy - py * qx;
+    }
+
+    let theta = cross.atan2(dot);
+    let (sin, cos) = theta.sin_cos();
+    Pose2D::new(
+        q_mean.x - (cos * p_mean.x - sin * p_mean.y),
+        q_mean.y - (sin * p_mean.x + cos * p_mean.y),
+        theta,
+    )
+}
+
+/// Covariance from the residual variance and the point-to-point Hessian.
+fn covariance(
+    pairs: &[(Point2, Point2)],
+    estimate: &Pose2D,
+    squared_error: f32,
+) -> Option<Covariance> {
+    let variance = squared_error / (pairs.len() as f32 - 3.0).max(1.0);
+    let mut hessian = [[0.0; 3]; 3];
+    for (p, _q) in pairs {
+        // Derivatives of the transformed point with respect to (x, y, theta)
+        let rows = [
+            [1.0, 0.0, -(p.y - estimate.y)],
+            [0.0, 1.0, p.x - estimate.x],
+        ];
+        for row in rows.iter() {
+            for i in 0..3 {
+                for j in 0..3 {
+                    hessian[i][j] += row[i] * row[j];
+                }
+            }
+        }
+    }
+    let inverse = invert(&hessian)?;
+    Some(scale(&inverse, variance))
+}
+
+fn invert(m: &Covariance) -> Option<Covariance> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if determinant == 0.0 || !determinant.is_finite() {
+        return None;
+    }
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    Some(scale(&adjugate, 1.0 / determinant))
+}
+
+fn scale(m: &Covariance, factor: f32) -> Covariance {
+    let mut result = *m;
+    for row in result.iter_mut() {
+        for value in row.iter_mut() {
+            *value *= factor;
+        }
+    }
+    result
+}
+
+fn add(a: &Covariance, b: &Covariance) -> Covariance {
+    let mut result = *a;
+    for i in 0..3 {
+        for j in 0..3 {
+            result[i][j] += b[i][j];
+        }
+    }
+    result
+}
+
+fn multiply(a: &Covariance, b: &Covariance) -> Covariance {
+    let mut result = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            for k in 0..3 {
+                result[i][j] += a[i][k] * b[k][j];
+            }
+        }
+    }
+    result
+}
+
+fn transpose(m: &Covariance) -> Covariance {
+    let mut result = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            result[i][j] = m[j][i];
+        }
+    }
+    result
+}
+
+fn multiply_vector(m: &Covariance, v: &[f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            result[i] += m[i][j] * v[j];
+        }
+    }
+    result
+}
+
+/// Uncertainty of wheel odometry, growing with the distance and angle travelled.
+#[derive(Debug, Clone, Copy)]
+pub struct OdometryNoise {
+    /// Standard deviation per meter driven.
+    pub translation: f32,
+    /// Standard deviation per radian turned.
+    pub rotation: f32,
+    /// Standard deviation added to every step, in meters and radians.
+    pub floor: f32,
+}
+
+impl Default for OdometryNoise {
+    fn default() -> Self {
+        Self {
+            translation: 0.1,
+            rotation: 0.2,
+            floor: 0.005,
+        }
+    }
+}
+
+impl OdometryNoise {
+    pub fn covariance(&self, delta: &Pose2D) -> Covariance {
+        let distance = delta.x.hypot(delta.y);
+        let translation = (self.translation * distance + self.floor).powi(2);
+        let rotation = (self.rotation * delta.theta.abs() + self.floor).powi(2);
+        [
+            [translation, 0.0, 0.0],
+            [0.0, translation, 0.0],
+            [0.0, 0.0, rotation],
+        ]
+    }
+}
+
+/// Information-weighted mean of the odometry delta and a scan match of the same motion.
+pub fn fuse(
+    odometry: &Pose2D,
+    odometry_covariance: &Covariance,
+    scan_match: &ScanMatch,
+) -> Option<(Pose2D, Covariance)> {
+    let odometry_information = invert(odometry_covariance)?;
+    let match_information = invert(&scan_match.covariance)?;
+    let covariance = invert(&add(&odometry_information, &match_information))?;
+
+    let odometry_vector = [odometry.x, odometry.y, odometry.theta];
+    // Keep both headings on the same side of the wrap-around
+    let match_vector = [
+        scan_match.pose.x,
+        scan_match.pose.y,
+        odometry.theta + normalize_angle(scan_match.pose.theta - odometry.theta),
+    ];
+    let a = multiply_vector(&odometry_information, &odometry_vector);
+    let b = multiply_vector(&match_information, &match_vector);
+    let mean = multiply_vector(&covariance, &[a[0] + b[0], a[1] + b[1], a[2] + b[2]]);
+
+    Some((Pose2D::new(mean[0], mean[1], mean[2]), covariance))
+}
+
+/// Pose estimate from wheel odometry corrected by matching consecutive LDS scans.
+pub struct FusedOdometry {
+    pub projector: ScanProjector,
+    pub matcher: ScanMatcher,
+    pub noise: OdometryNoise,
+    odometry: WheelOdometry,
+    previous_scan: Option<Vec<Point2>>,
+    pose: Pose2D,
+    covariance: Covariance,
+}
+
+impl FusedOdometry {
+    pub fn new(projector: ScanProjector) -> Self {
+        Self {
+            projector,
+            matcher: ScanMatcher::default(),
+            noise: OdometryNoise::default(),
+            odometry: WheelOdometry::default(),
+            previous_scan: None,
+            pose: Pose2D::default(),
+            covariance: [[0.0; 3]; 3],
+        }
+    }
+
+    pub fn pose(&self) -> Pose2D {
+        self.pose
+    }
+
+    pub fn covariance(&self) -> Covariance {
+        self.covariance
+    }
+
+    /// Pose from the wheel encoders alone, for comparison.
+    pub fn odometry_pose(&self) -> Pose2D {
+        self.odometry.pose()
+    }
+
+    /// Add a scan taken at the wheel positions in `motors` and return the fused pose.
+    pub fn update(&mut self, motors: &MotorStatus, ranges: &[f32]) -> Pose2D {
+        let odometry_delta = self.odometry.update(motors);
+        let odometry_covariance = self.noise.covariance(&odometry_delta);
+        let scan = self.projector.project(ranges);
+
+        let fused = self
+            .previous_scan
+            .as_ref()
+            .and_then(|previous| self.matcher.match_scans(previous, &scan, &odometry_delta))
+            .filter(|scan_match| scan_match.converged)
+            .and_then(|scan_match| fuse(&odometry_delta, &odometry_covariance, &scan_match));
+
+        let (delta, delta_covariance) = match fused {
+            Some(fused) => fused,
+            None => {
+                log::debug!("Scan match failed or did not converge, using odometry only");
+                (odometry_delta, odometry_covariance)
+            }
+        };
+
+        self.propagate(&delta, &delta_covariance);
+        self.previous_scan = Some(scan);
+        self.pose
+    }
+
+    fn propagate(&mut self, delta: &Pose2D, delta_covariance: &Covariance) {
+        let (sin, cos) = self.pose.theta.sin_cos();
+        // Jacobians of composing the delta onto the pose, with respect to the pose and the delta
+        let pose_jacobian = [
+            [1.0, 0.0, -sin * delta.x - cos * delta.y],
+            [0.0, 1.0, cos * delta.x - sin * delta.y],
+            [0.0, 0.0, 1.0],
+        ];
+        let delta_jacobian = [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]];
+
+        self.covariance = add(
+            &multiply(
+                &multiply(&pose_jacobian, &self.covariance),
+                &transpose(&pose_jacobian),
+            ),
+            &multiply(
+                &multiply(&delta_jacobian, delta_covariance),
+                &transpose(&delta_jacobian),
+            ),
+        );
+        self.pose = self.pose.compose(delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scan of one point per degree around an irregular room, so that no part
+    /// of it looks like another.
+    fn room() -> Vec<Point2> {
+        (0..360)
+            .map(|degree| {
+                let angle = (degree as f32).to_radians();
+                let range = 2.0 + 0.4 * (3.0 * angle).sin() + 0.2 * (5.0 * angle).cos();
+                Point2::new(range * angle.cos(), range * angle.sin())
+            })
+            .collect()
+    }
+
+    fn diagonal(x: f32, y: f32, theta: f32) -> Covariance {
+        [[x, 0.0, 0.0], [0.0, y, 0.0], [0.0, 0.0, theta]]
+    }
+
+    #[test]
+    fn recovers_translation_and_rotation() {
+        let reference = room();
+        let motion = Pose2D::new(0.1, -0.05, 0.05);
+        let current: Vec<Point2> = reference
+            .iter()
+            .map(|point| motion.inverse().transform(point))
+            .collect();
+
+        // Start from an odometry estimate that is a few centimeters off
+        let initial = Pose2D::new(0.07, -0.02, 0.045);
+        let found = ScanMatcher::default()
+            .match_scans(&reference, &current, &initial)
+            .unwrap();
+        assert!(found.converged);
+        assert!((found.pose.x - motion.x).abs() < 1e-3, "{:?}", found.pose);
+        assert!((found.pose.y - motion.y).abs() < 1e-3, "{:?}", found.pose);
+        assert!(
+            (found.pose.theta - motion.theta).abs() < 1e-3,
+            "{:?}",
+            found.pose
+        );
+        assert!(found.error < 1e-3);
+    }
+
+    #[test]
+    fn ignores_points_that_are_not_finite() {
+        let mut reference = room();
+        reference[10] = Point2::new(f32::NAN, 0.0);
+        let motion = Pose2D::new(0.05, 0.0, 0.0);
+        let mut current: Vec<Point2> = room()
+            .iter()
+            .map(|point| motion.inverse().transform(point))
+            .collect();
+        current[20] = Point2::new(f32::INFINITY, f32::NAN);
+
+        let found = ScanMatcher::default()
+            .match_scans(&reference, &current, &motion)
+            .unwrap();
+        assert!(found.converged);
+        assert!(found.pose.x.is_finite() && (found.pose.x - motion.x).abs() < 1e-3);
+        assert_eq!(found.correspondences, 359);
+    }
+
+    #[test]
+    fn uses_odometry_when_the_match_does_not_converge() {
+        let ranges: Vec<f32> = room().iter().map(|point| point.x.hypot(point.y)).collect();
+        let motors = |position| MotorStatus {
+            left_wheel_position_in_mm: position,
+            right_wheel_position_in_mm: position,
+            ..MotorStatus::default()
+        };
+
+        let mut fused = FusedOdometry::new(ScanProjector::default());
+        // A single iteration cannot converge from the odometry estimate
+        fused.matcher.config.max_iterations = 1;
+        fused.update(&motors(0), &ranges);
+        let pose = fused.update(&motors(100), &ranges);
+        assert_eq!(pose, fused.odometry_pose());
+        assert!((pose.x - 0.1).abs() < 1e-6, "{:?}", pose);
+    }
+
+    #[test]
+    fn gives_up_without_overlap() {
+        let reference = room();
+        let current: Vec<Point2> = reference
+            .iter()
+            .map(|point| Point2::new(point.x + 10.0, point.y))
+            .collect();
+        assert!(ScanMatcher::default()
+            .match_scans(&reference, &current, &Pose2D::default())
+            .is_none());
+    }
+
+    #[test]
+    fn fuse_weights_by_covariance() {
+        let odometry = Pose2D::new(1.0, 0.0, 0.0);
+        let scan_match = ScanMatch {
+            pose: Pose2D::new(0.0, 1.0, 0.3),
+            covariance: diagonal(1.0, 0.01, 0.01),
+            error: 0.0,
+            correspondences: 100,
+            iterations: 1,
+            converged: true,
+        };
+
+        // Odometry is trusted along x and the scan match along y and theta
+        let (pose, covariance) = fuse(&odometry, &diagonal(0.01, 1.0, 1.0), &scan_match).unwrap();
+        assert!((pose.x - 1.0 / 1.01).abs() < 1e-4, "{:?}", pose);
+        assert!((pose.y - 1.0 / 1.01).abs() < 1e-4, "{:?}", pose);
+        assert!((pose.theta - 0.3 / 1.01).abs() < 1e-4, "{:?}", pose);
+        for (i, row) in covariance.iter().enumerate() {
+            assert!((row[i] - 0.01 / 1.01).abs() < 1e-5, "{:?}", covariance);
+        }
+    }
+
+    #[test]
+    fn fuse_of_equal_covariances_is_the_mean() {
+        let odometry = Pose2D::new(0.2, 0.0, 0.1);
+        let scan_match = ScanMatch {
+            pose: Pose2D::new(0.4, 0.2, 0.3),
+            covariance: diagonal(0.1, 0.1, 0.1),
+            error: 0.0,
+            correspondences: 100,
+            iterations: 1,
+            converged: true,
+        };
+
+        let (pose, _) = fuse(&odometry, &diagonal(0.1, 0.1, 0.1), &scan_match).unwrap();
+        assert!((pose.x - 0.3).abs() < 1e-5);
+        assert!((pose.y - 0.1).abs() < 1e-5);
+        assert!((pose.theta - 0.2).abs() < 1e-5);
+    }
+}
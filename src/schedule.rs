@@ -0,0 +1,238 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::Weekday;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanType {
+    House,
+    /// Not accepted by `SetSchedule` on all firmware.
+    Spot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleEntry {
+    pub day: Weekday,
+    pub hour: u8,
+    pub minute: u8,
+    pub clean: CleanType,
+}
+
+impl ScheduleEntry {
+    pub fn new(day: Weekday, hour: u8, minute: u8, clean: CleanType) -> Self {
+        Self {
+            day,
+            hour,
+            minute,
+            clean,
+        }
+    }
+
+    /// The arguments of `SetSchedule` for this entry.
+    pub fn arguments(&self) -> String {
+        let clean = match self.clean {
+            CleanType::House => "house",
+            CleanType::Spot => "spot",
+        };
+        format!(
+            "day {} hour {} min {} {}",
+            self.day.num_days_from_sunday(),
+            self.hour,
+            self.minute,
+            clean
+        )
+    }
+}
+
+/// The weekly cleaning schedule, with at most one cleaning per day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub enabled: bool,
+    /// Ordered from Sunday, days without an entry have no cleaning.
+    pub entries: Vec<ScheduleEntry>,
+}
+
+impl Schedule {
+    pub fn entry(&self, day: Weekday) -> Option<&ScheduleEntry> {
+        self.entries.iter().find(|entry| entry.day == day)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid schedule line {0:?}")]
+pub struct InvalidScheduleError(String);
+
+/// Days in the order `GetSchedule` lists them.
+pub const DAYS: [Weekday; 7] = [
+    Weekday::Sun,
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+];
+
+impl FromStr for Schedule {
+    type Err = InvalidScheduleError;
+
+    /// Parse a `GetSchedule` reply, such as
+    ///
+    /// ```text
+    /// Schedule is Enabled
+    /// Sun 00:00 - None -
+    /// Mon 09:30 H
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut schedule = Schedule {
+            enabled: false,
+            entries: vec![],
+        };
+
+        for line in s
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+        {
+            let invalid = || InvalidScheduleError(line.to_string());
+            if let Some(state) = line.strip_prefix("Schedule is ") {
+                schedule.enabled = state.eq_ignore_ascii_case("enabled");
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (day, time, clean) = match words.as_slice() {
+                [day, time, clean] => (day, time, clean),
+                [_day, _time, "-", "None", "-"] => continue,
+                _ => return Err(invalid()),
+            };
+            let day: Weekday = day.parse().map_err(|_| invalid())?;
+            let mut time = time.splitn(2, ':').map(|part| part.parse::<u8>());
+            let (hour, minute) = match (time.next(), time.next()) {
+                (Some(Ok(hour)), Some(Ok(minute))) if hour < 24 && minute < 60 => (hour, minute),
+                _ => return Err(invalid()),
+            };
+            // Older firmware marks a house cleaning as a regular one
+            let clean = match *clean {
+                "H" | "R" => CleanType::House,
+                "S" => CleanType::Spot,
+                _ => return Err(invalid()),
+            };
+            schedule
+                .entries
+                .push(ScheduleEntry::new(day, hour, minute, clean));
+        }
+
+        schedule
+            .entries
+            .sort_by_key(|entry| entry.day.num_days_from_sunday());
+        Ok(schedule)
+    }
+}
+
+impl Display for Schedule {
+    /// Format like a `GetSchedule` reply.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = if self.enabled { "Enabled" } else { "Disabled" };
+        writeln!(f, "Schedule is {}", state)?;
+        for day in DAYS.iter() {
+            match self.entry(*day) {
+                Some(entry) => {
+                    let clean = match entry.clean {
+                        CleanType::House => "H",
+                        CleanType::Spot => "S",
+                    };
+                    writeln!(f, "{} {:02}:{:02} {}", day, entry.hour, entry.minute, clean)?
+                }
+                None => writeln!(f, "{} 00:00 - None -", day)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries, NeatoRobot};
+
+    const REPLY: &str = "Schedule is Enabled\r
+Sun 00:00 - None -\r
+Mon 09:30 H\r
+Tue 00:00 - None -\r
+Wed 18:05 S\r
+Thu 00:00 - None -\r
+Fri 07:00 H\r
+Sat 00:00 - None -\r
+";
+
+    fn schedule() -> Schedule {
+        Schedule {
+            enabled: true,
+            entries: vec![
+                ScheduleEntry::new(Weekday::Mon, 9, 30, CleanType::House),
+                ScheduleEntry::new(Weekday::Wed, 18, 5, CleanType::Spot),
+                ScheduleEntry::new(Weekday::Fri, 7, 0, CleanType::House),
+            ],
+        }
+    }
+
+    #[test]
+    fn parses_reply() {
+        assert_eq!(REPLY.parse::<Schedule>().unwrap(), schedule());
+    }
+
+    #[test]
+    fn reply_round_trips() {
+        let text = REPLY.parse::<Schedule>().unwrap().to_string();
+        assert_eq!(text, REPLY.replace('\r', ""));
+    }
+
+    #[test]
+    fn schedule_round_trips() {
+        let mut schedule = schedule();
+        schedule.enabled = false;
+        assert_eq!(schedule.to_string().parse::<Schedule>().unwrap(), schedule);
+    }
+
+    #[test]
+    fn parses_regular_cleaning_as_house() {
+        let schedule: Schedule = "Schedule is Disabled\nTue 23:59 R\n".parse().unwrap();
+        assert!(!schedule.enabled);
+        assert_eq!(
+            schedule.entries,
+            vec![ScheduleEntry::new(Weekday::Tue, 23, 59, CleanType::House)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_time() {
+        assert!("Mon 24:00 H".parse::<Schedule>().is_err());
+        assert!("Mon 9 H".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn sets_every_day() {
+        let port = ScriptedPort::new()
+            .expect("setschedule day 0 hour 0 min 0 none", "\x1a")
+            .expect("setschedule day 1 hour 9 min 30 house", "\x1a")
+            .expect("setschedule day 2 hour 0 min 0 none", "\x1a")
+            .expect("setschedule day 3 hour 18 min 5 spot", "\x1a")
+            .expect("setschedule day 4 hour 0 min 0 none", "\x1a")
+            .expect("setschedule day 5 hour 7 min 0 house", "\x1a")
+            .expect("setschedule day 6 hour 0 min 0 none", "\x1a")
+            .expect("setschedule on", "\x1a")
+            .expect("getschedule", &format!("getschedule\r\n{}\x1a", REPLY));
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        robot.set_schedule(&schedule()).unwrap();
+        assert_eq!(robot.get_schedule().unwrap(), schedule());
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn formats_set_schedule_arguments() {
+        let entry = ScheduleEntry::new(Weekday::Sat, 8, 15, CleanType::House);
+        assert_eq!(entry.arguments(), "day 6 hour 8 min 15 house");
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serialport::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits,
+};
+
+#[derive(Debug, Default)]
+struct Script {
+    settings: SerialPortSettings,
+    exchanges: VecDeque<(String, Vec<u8>)>,
+    incoming: VecDeque<u8>,
+    partial_line: Vec<u8>,
+    written: Vec<String>,
+}
+
+/// An in-memory stand-in for the robot's serial port that answers commands from a script.
+///
+/// Each command written must match the next scripted one, after which its reply becomes
+/// available for reading. Reading with nothing pending times out like a real port.
+/// Clones share the same script, so a test can keep one to inspect what was written.
+#[derive(Debug, Default, Clone)]
+pub struct ScriptedPort {
+    script: Arc<Mutex<Script>>,
+}
+
+impl ScriptedPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect `command`, without line ending, and answer it with `reply`.
+    pub fn expect(self, command: &str, reply: &str) -> Self {
+        self.expect_bytes(command, reply.as_bytes())
+    }
+
+    pub fn expect_bytes(self, command: &str, reply: &[u8]) -> Self {
+        self.script
+            .lock()
+            .unwrap()
+            .exchanges
+            .push_back((command.to_string(), reply.to_vec()));
+        self
+    }
+
+    /// Make `data` readable without waiting for a command.
+    pub fn push_reply(&self, data: &[u8]) {
+        self.script.lock().unwrap().incoming.extend(data);
+    }
+
+    /// Commands written so far, without line endings.
+    pub fn written(&self) -> Vec<String> {
+        self.script.lock().unwrap().written.clone()
+    }
+
+    /// Whether every scripted command has been sent.
+    pub fn is_finished(&self) -> bool {
+        self.script.lock().unwrap().exchanges.is_empty()
+    }
+}
+
+impl Script {
+    fn receive_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let command = String::from_utf8_lossy(line).trim().to_string();
+        if command.is_empty() {
+            return Ok(());
+        }
+        self.written.push(command.clone());
+
+        match self.exchanges.pop_front() {
+            Some((expected, reply)) if expected == command => {
+                self.incoming.extend(reply);
+                Ok(())
+            }
+            Some((expected, _reply)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Expected {:?} but got {:?}", expected, command),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unexpected command {:?}", command),
+            )),
+        }
+    }
+}
+
+impl io::Read for ScriptedPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut script = self.script.lock().unwrap();
+        if script.incoming.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+        let count = buf.len().min(script.incoming.len());
+        for (byte, value) in buf.iter_mut().zip(script.incoming.drain(..count)) {
+            *byte = value;
+        }
+        Ok(count)
+    }
+}
+
+impl io::Write for ScriptedPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut script = self.script.lock().unwrap();
+        for byte in buf {
+            if *byte == b'\n' {
+                let line = std::mem::take(&mut script.partial_line);
+                script.receive_line(&line)?;
+            } else {
+                script.partial_line.push(*byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for ScriptedPort {
+    fn name(&self) -> Option<String> {
+        Some(String::from("scripted"))
+    }
+
+    fn settings(&self) -> SerialPortSettings {
+        self.script.lock().unwrap().settings
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings().baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings().data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings().flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings().parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings().stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.settings().timeout
+    }
+
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings = *settings;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.script.lock().unwrap().settings.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.script.lock().unwrap().incoming.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        let mut script = self.script.lock().unwrap();
+        match buffer_to_clear {
+            ClearBuffer::Input => script.incoming.clear(),
+            ClearBuffer::Output => script.partial_line.clear(),
+            ClearBuffer::All => {
+                script.incoming.clear();
+                script.partial_line.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::NeatoRobot;
+
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub stamp: Instant,
+    pub ranges: Vec<f32>,
+}
+
+type Job<R> = Box<dyn FnOnce(&mut R) + Send>;
+
+/// Number of scans buffered for a slow consumer before new ones are dropped.
+const SCAN_BUFFER: usize = 4;
+
+/// Weight of the newest interval in the scan rate estimate.
+const RATE_SMOOTHING: f32 = 0.2;
+
+/// How an `LdsStream` reacts to failed scans.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamConfig {
+    /// Consecutive failed scans after which the stream gives up, so a disconnected
+    /// robot does not keep the thread spinning.
+    pub max_scan_errors: u32,
+    /// Pause after a failed scan, growing with every further failure up to `max_backoff`.
+    pub error_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            max_scan_errors: 10,
+            error_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Requests LDS scans back to back on a background thread that owns the robot.
+///
+/// Other commands are queued with `execute` and run between scans, one per scan,
+/// so neither the scans nor the commands can starve each other.
+pub struct LdsStream<R: NeatoRobot + Send + 'static> {
+    scans: mpsc::Receiver<Scan>,
+    jobs: mpsc::Sender<Job<R>>,
+    stop: Arc<AtomicBool>,
+    rate: Arc<Mutex<f32>>,
+    worker: Option<thread::JoinHandle<R>>,
+}
+
+impl<R: NeatoRobot + Send + 'static> LdsStream<R> {
+    /// Start streaming. The LDS must already be rotating.
+    pub fn spawn(robot: R) -> Self {
+        Self::spawn_with_config(robot, StreamConfig::default())
+    }
+
+    /// Like `spawn`, handling failed scans as `config` says.
+    pub fn spawn_with_config(robot: R, config: StreamConfig) -> Self {
+        let (scan_tx, scans) = mpsc::sync_channel(SCAN_BUFFER);
+        let (jobs, job_rx) = mpsc::channel::<Job<R>>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let rate = Arc::new(Mutex::new(0.0));
+
+        let worker = {
+            let stop = stop.clone();
+            let rate = rate.clone();
+            thread::spawn(move || {
+                let mut robot = robot;
+                let mut last_scan: Option<Instant> = None;
+                let mut errors = 0;
+
+                while !stop.load(Ordering::Relaxed) {
+                    match scan(&mut robot) {
+                        Ok(scan) => {
+                            errors = 0;
+                            if let Some(last) = last_scan {
+                                update_rate(&rate, scan.stamp - last);
+                            }
+                            last_scan = Some(scan.stamp);
+                            match scan_tx.try_send(scan) {
+                                Ok(()) => {}
+                                Err(mpsc::TrySendError::Full(_)) => {
+                                    log::debug!("Scan consumer is falling behind, dropping scan")
+                                }
+                                Err(mpsc::TrySendError::Disconnected(_)) => break,
+                            }
+                        }
+                        Err(err) => {
+                            errors += 1;
+                            log::error!("Could not get scan: {:?}", err);
+                            if errors >= config.max_scan_errors {
+                                log::error!("Stopping LDS stream after {} failed scans", errors);
+                                break;
+                            }
+                            thread::sleep((config.error_backoff * errors).min(config.max_backoff));
+                        }
+                    }
+
+                    if let Ok(job) = job_rx.try_recv() {
+                        job(&mut robot);
+                    }
+                }
+
+                // Do not leave callers of execute waiting forever
+                while let Ok(job) = job_rx.try_recv() {
+                    job(&mut robot);
+                }
+                robot
+            })
+        };
+
+        Self {
+            scans,
+            jobs,
+            stop,
+            rate,
+            worker: Some(worker),
+        }
+    }
+
+    /// Block until the next scan arrives. Returns `None` once the stream has stopped.
+    pub fn recv(&self) -> Option<Scan> {
+        self.scans.recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Scan> {
+        self.scans.recv_timeout(timeout).ok()
+    }
+
+    pub fn iter(&self) -> mpsc::Iter<'_, Scan> {
+        self.scans.iter()
+    }
+
+    /// Run `command` on the robot between two scans and wait for its result.
+    pub fn execute<T, F>(&self, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut R) -> T + Send + 'static,
+    {
+        let (result_tx, result_rx) = mpsc::channel();
+        self.jobs
+            .send(Box::new(move |robot: &mut R| {
+                let _ = result_tx.send(command(robot));
+            }))
+            .map_err(|_| anyhow!("LDS stream is not running"))?;
+        result_rx
+            .recv()
+            .map_err(|_| anyhow!("LDS stream stopped before running the command"))
+    }
+
+    /// Scans per second actually achieved, smoothed over the last few scans.
+    pub fn scan_rate(&self) -> f32 {
+        *self.rate.lock().unwrap()
+    }
+
+    /// Stop streaming and hand back the robot.
+    pub fn stop(mut self) -> Result<R> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.worker
+            .take()
+            .expect("LDS stream worker already joined")
+            .join()
+            .map_err(|_| anyhow!("LDS stream worker panicked"))
+    }
+}
+
+impl<R: NeatoRobot + Send + 'static> Drop for LdsStream<R> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn scan<R: NeatoRobot>(robot: &mut R) -> Result<Scan> {
+    robot.request_scan()?;
+    let ranges = robot.get_scan_ranges()?;
+    Ok(Scan {
+        stamp: Instant::now(),
+        ranges,
+    })
+}
+
+fn update_rate(rate: &Mutex<f32>, interval: Duration) {
+    let seconds = interval.as_secs_f32();
+    if seconds <= 0.0 {
+        return;
+    }
+    let mut rate = rate.lock().unwrap();
+    *rate = if *rate == 0.0 {
+        1.0 / seconds
+    } else {
+        (1.0 - RATE_SMOOTHING) * *rate + RATE_SMOOTHING / seconds
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries};
+
+    fn scan_reply() -> String {
+        let mut reply =
+            String::from("getldsscan\r\nAngleInDegrees,DistInMM,Intensity,ErrorCodeHEX\r\n");
+        for angle in 0..360 {
+            reply.push_str(&format!("{},1000,100,0\r\n", angle));
+        }
+        reply.push_str("ROTATION_SPEED,5.00\r\n");
+        reply
+    }
+
+    #[test]
+    fn runs_jobs_between_scans_and_measures_rate() {
+        let port = (0..5).fold(ScriptedPort::new(), |port, _| {
+            port.expect("getldsscan", &scan_reply())
+        });
+        let stream = LdsStream::spawn(DSeries::new(Box::new(port.clone())));
+
+        let first = stream.recv().unwrap();
+        assert_eq!(first.ranges.len(), 360);
+        assert_eq!(first.ranges[0], 1.0);
+        stream.recv().unwrap();
+
+        let written = {
+            let port = port.clone();
+            stream.execute(move |_robot| port.written().len()).unwrap()
+        };
+        assert!(written >= 2, "job ran before the scans");
+        assert!(stream.scan_rate() > 0.0);
+
+        stream.stop().unwrap();
+        assert!(port.written().iter().all(|command| command == "getldsscan"));
+    }
+
+    #[test]
+    fn gives_up_after_repeated_errors() {
+        let config = StreamConfig {
+            max_scan_errors: 3,
+            error_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+        let stream =
+            LdsStream::spawn_with_config(DSeries::new(Box::new(ScriptedPort::new())), config);
+        assert!(stream.recv().is_none());
+        stream.stop().unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use std::{convert::Infallible, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{IntField, SimpleFloatField, UnitFloatField};
+
+/// A value in a `Table`, as the first field parser that accepts it reads it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i32),
+    Float(f32),
+    Text(String),
+}
+
+/// A `Name,Value` or `Name,Unit,Value` row of a `Table`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Row {
+    pub name: String,
+    pub unit: Option<String>,
+    pub value: Value,
+}
+
+/// Any comma separated reply, such as that of `GetAnalogSensors`, read without
+/// knowing its fields.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Table {
+    /// Names of the columns, empty when the reply has no header row.
+    pub columns: Vec<String>,
+    pub rows: Vec<Row>,
+    /// Lines without commas, such as titles, help text or errors.
+    pub text: Vec<String>,
+}
+
+impl Table {
+    pub fn get(&self, name: &str) -> Option<&Row> {
+        self.rows.iter().find(|row| row.name == name)
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        match self.get(name)?.value {
+            Value::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Integers are converted, since a float reading may happen to be whole.
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.get(name)?.value {
+            Value::Int(value) => Some(value as f32),
+            Value::Float(value) => Some(value),
+            Value::Text(_) => None,
+        }
+    }
+
+    /// 1 is true and any other number false, as in `GetDigitalSensors`, whether
+    /// or not the row has a unit.
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)?.value {
+            Value::Int(value) => Some(value == 1),
+            Value::Float(value) => Some(value == 1.0),
+            Value::Text(_) => None,
+        }
+    }
+
+    pub fn get_text(&self, name: &str) -> Option<&str> {
+        match &self.get(name)?.value {
+            Value::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+fn is_number(field: &str) -> bool {
+    field.trim().parse::<f64>().is_ok()
+}
+
+fn parse_row(line: &str, fields: &[&str]) -> Row {
+    let name = fields[0].to_string();
+    if fields.len() == 3 {
+        let value = match UnitFloatField::from_str(line) {
+            Ok(field) => Value::Float(field.value),
+            Err(_) => Value::Text(fields[2].to_string()),
+        };
+        return Row {
+            name,
+            unit: Some(fields[1].to_string()),
+            value,
+        };
+    } else if fields.len() == 2 {
+        if let Ok(field) = IntField::from_str(line) {
+            return Row {
+                name,
+                unit: None,
+                value: Value::Int(field.value),
+            };
+        }
+        if let Ok(field) = SimpleFloatField::from_str(line) {
+            return Row {
+                name,
+                unit: None,
+                value: Value::Float(field.value),
+            };
+        }
+    }
+    Row {
+        name,
+        unit: None,
+        value: Value::Text(fields[1..].join(",")),
+    }
+}
+
+impl FromStr for Table {
+    type Err = Infallible;
+
+    /// Parse a reply of comma separated rows. The first row without numbers is
+    /// taken as the header, and values that are not numbers are kept as text.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table = Table::default();
+        for line in s
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+        {
+            if !line.contains(',') {
+                table.text.push(line.to_string());
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+            let is_header = table.columns.is_empty()
+                && table.rows.is_empty()
+                && !fields.iter().any(|field| is_number(field));
+            if is_header {
+                table.columns = fields.iter().map(|field| field.to_string()).collect();
+            } else {
+                table.rows.push(parse_row(line, &fields));
+            }
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scripted_port::ScriptedPort, DSeries, NeatoRobot};
+
+    fn row(name: &str, unit: Option<&str>, value: Value) -> Row {
+        Row {
+            name: name.to_string(),
+            unit: unit.map(String::from),
+            value,
+        }
+    }
+
+    #[test]
+    fn reads_name_value_rows_under_header() {
+        let table: Table = "Label,Value\r\nFuelPercent,80\r\nVBattV,16.25\r\nCharging,No\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(table.columns, vec!["Label", "Value"]);
+        assert_eq!(
+            table.rows,
+            vec![
+                row("FuelPercent", None, Value::Int(80)),
+                row("VBattV", None, Value::Float(16.25)),
+                row("Charging", None, Value::Text(String::from("No"))),
+            ]
+        );
+        assert!(table.text.is_empty());
+        assert_eq!(table.get_int("FuelPercent"), Some(80));
+        assert_eq!(table.get_float("FuelPercent"), Some(80.0));
+        assert_eq!(table.get_int("VBattV"), None);
+        assert_eq!(table.get_text("Charging"), Some("No"));
+        assert_eq!(table.get("Missing"), None);
+    }
+
+    #[test]
+    fn reads_name_unit_value_rows() {
+        let table: Table =
+            "SensorName,Unit,Value\r\nBatteryVoltage,mV,16250\r\nBatteryTemp0InC, C ,31.5\r\n"
+                .parse()
+                .unwrap();
+        assert_eq!(table.columns, vec!["SensorName", "Unit", "Value"]);
+        assert_eq!(
+            table.rows,
+            vec![
+                row("BatteryVoltage", Some("mV"), Value::Float(16250.0)),
+                row("BatteryTemp0InC", Some("C"), Value::Float(31.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_unit_of_non_numeric_values() {
+        let table: Table = "Name,Unit,Value\r\nBrushMode,mode,Auto\r\nLSIDEBIT,bit,1\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            table.rows,
+            vec![
+                row("BrushMode", Some("mode"), Value::Text(String::from("Auto"))),
+                row("LSIDEBIT", Some("bit"), Value::Float(1.0)),
+            ]
+        );
+        assert_eq!(table.get_text("BrushMode"), Some("Auto"));
+        assert_eq!(table.get_bool("BrushMode"), None);
+        assert_eq!(table.get_bool("LSIDEBIT"), Some(true));
+    }
+
+    #[test]
+    fn only_the_first_row_without_numbers_is_the_header() {
+        let table: Table = "SNSR_DC_JACK_CONNECT,0\r\nName,Value\r\n".parse().unwrap();
+        assert!(table.columns.is_empty());
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.get_bool("SNSR_DC_JACK_CONNECT"), Some(false));
+        assert_eq!(table.get_text("Name"), Some("Value"));
+    }
+
+    #[test]
+    fn keeps_lines_without_commas_as_text() {
+        let table: Table = "Help Strlen = 1792\r\n\r\nClean - Starts a cleaning\r\n"
+            .parse()
+            .unwrap();
+        assert!(table.columns.is_empty());
+        assert!(table.rows.is_empty());
+        assert_eq!(
+            table.text,
+            vec!["Help Strlen = 1792", "Clean - Starts a cleaning"]
+        );
+    }
+
+    #[test]
+    fn keeps_rows_of_other_widths_as_text() {
+        let table: Table = "Time,Event,Data,Code\r\n1023,Boot,0,7\r\n".parse().unwrap();
+        assert_eq!(
+            table.rows,
+            vec![row("1023", None, Value::Text(String::from("Boot,0,7")))]
+        );
+    }
+
+    #[test]
+    fn raw_command_parses_any_reply() {
+        let port = ScriptedPort::new()
+            .expect(
+                "getaccel",
+                "getaccel\r\nLabel,Value\r\nPitchInDegrees, -0.70\r\nXInG,-0.012\r\n\x1a",
+            )
+            .expect("help", "help\r\nHelp Strlen = 1792\r\n\x1a");
+        let mut robot = DSeries::new(Box::new(port.clone()));
+
+        let accel = robot.raw_command("getaccel").unwrap();
+        assert_eq!(accel.columns, vec!["Label", "Value"]);
+        assert_eq!(accel.get_float("PitchInDegrees"), Some(-0.7));
+        assert_eq!(accel.get_float("XInG"), Some(-0.012));
+
+        let help = robot.raw_command("help").unwrap();
+        assert_eq!(help.text, vec!["Help Strlen = 1792"]);
+        assert!(port.is_finished());
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::DSeries;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line.
+    JsonLines,
+    /// Comma separated values with a header row. Arrays and objects, such as the
+    /// ranges of a scan, are written as JSON in a single column.
+    Csv,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// When to start a new file. A CSV file is also rotated when its columns change.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RotationPolicy {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+struct StreamFile {
+    writer: BufWriter<File>,
+    opened: SystemTime,
+    bytes: u64,
+    columns: Vec<String>,
+}
+
+/// Writes timestamped telemetry records to one rotating file per stream.
+///
+/// Files are named `<stream>-<unix seconds>-<sequence>.<jsonl|csv>` in the output directory.
+pub struct TelemetryRecorder {
+    directory: PathBuf,
+    format: Format,
+    policy: RotationPolicy,
+    files: HashMap<String, StreamFile>,
+    sequence: u64,
+}
+
+impl TelemetryRecorder {
+    pub fn new(directory: &Path, format: Format, policy: RotationPolicy) -> Result<Self> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Could not create {:?}", directory))?;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            format,
+            policy,
+            files: HashMap::new(),
+            sequence: 0,
+        })
+    }
+
+    /// Record the status snapshots cached in `robot` by its `get_*` methods.
+    pub fn record_snapshot(&mut self, robot: &DSeries) -> Result<()> {
+        self.record("motor", robot.motor_status())?;
+        self.record("analog", robot.analog_sensor_status())?;
+        self.record("digital", robot.digital_sensor_status())?;
+        self.record("charger", robot.charger_status())?;
+        Ok(())
+    }
+
+    /// Record ranges as returned by `get_scan_ranges`.
+    pub fn record_scan(&mut self, ranges: &[f32]) -> Result<()> {
+        #[derive(Serialize)]
+        struct Scan<'a> {
+            ranges: &'a [f32],
+        }
+        self.record("scan", &Scan { ranges })
+    }
+
+    /// Record any serializable struct to the file for `stream`, stamped with the current time.
+    pub fn record<T: Serialize>(&mut self, stream: &str, value: &T) -> Result<()> {
+        self.record_at(stream, value, SystemTime::now())
+    }
+
+    /// Record `value` as taken at `time`, which also decides when files rotate by age.
+    pub fn record_at<T: Serialize>(
+        &mut self,
+        stream: &str,
+        value: &T,
+        time: SystemTime,
+    ) -> Result<()> {
+        let timestamp = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let fields = match serde_json::to_value(value)? {
+            Value::Object(fields) => fields,
+            other => {
+                let mut fields = Map::new();
+                fields.insert(String::from("value"), other);
+                fields
+            }
+        };
+
+        let (line, columns) = match self.format {
+            Format::JsonLines => {
+                let mut record = fields;
+                record.insert(String::from("timestamp"), Value::from(timestamp));
+                (Value::Object(record).to_string(), vec![])
+            }
+            Format::Csv => csv_row(timestamp, &fields),
+        };
+        self.write_line(stream, &line, columns, time)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        for file in self.files.values_mut() {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_line(
+        &mut self,
+        stream: &str,
+        line: &str,
+        columns: Vec<String>,
+        time: SystemTime,
+    ) -> Result<()> {
+        let rotate = match self.files.get(stream) {
+            Some(file) => {
+                matches!(self.policy.max_bytes, Some(max_bytes) if file.bytes >= max_bytes)
+                    || matches!(self.policy.max_age, Some(max_age) if time.duration_since(file.opened).unwrap_or_default() >= max_age)
+                    || file.columns != columns
+            }
+            None => true,
+        };
+
+        if rotate {
+            if let Some(mut file) = self.files.remove(stream) {
+                file.writer.flush()?;
+            }
+            let file = self.open(stream, columns, time)?;
+            self.files.insert(stream.to_string(), file);
+        }
+
+        let file = self
+            .files
+            .get_mut(stream)
+            .expect("stream file was just opened");
+        writeln!(file.writer, "{}", line)?;
+        file.bytes += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn open(&mut self, stream: &str, columns: Vec<String>, time: SystemTime) -> Result<StreamFile> {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // Never overwrite the files of an earlier recorder started in the same second
+        let (path, file) = loop {
+            let path = self.directory.join(format!(
+                "{}-{}-{}.{}",
+                stream,
+                seconds,
+                self.sequence,
+                self.format.extension()
+            ));
+            self.sequence += 1;
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Could not create {:?}", path))
+                }
+            }
+        };
+        log::info!("Writing {} telemetry to {:?}", stream, path);
+
+        let mut writer = BufWriter::new(file);
+        let mut bytes = 0;
+        if !columns.is_empty() {
+            let header = columns.join(",");
+            writeln!(writer, "{}", header)?;
+            bytes += header.len() as u64 + 1;
+        }
+
+        Ok(StreamFile {
+            writer,
+            opened: time,
+            bytes,
+            columns,
+        })
+    }
+}
+
+impl Drop for TelemetryRecorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::error!("Could not flush telemetry: {:?}", err);
+        }
+    }
+}
+
+/// The CSV row for a record and the column names it fills.
+fn csv_row(timestamp: f64, fields: &Map<String, Value>) -> (String, Vec<String>) {
+    let mut columns = vec![String::from("timestamp")];
+    let mut cells = vec![timestamp.to_string()];
+    for (name, value) in fields {
+        columns.push(name.clone());
+        cells.push(csv_cell(value));
+    }
+    (cells.join(","), columns)
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Array(_) | Value::Object(_) => csv_cell(&Value::String(value.to_string())),
+        Value::String(text) if text.contains(&[',', '"', '\n', '\r'][..]) => {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        }
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("neato-telemetry-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn files(directory: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn writes_csv_header_and_arrays_in_one_column() {
+        let directory = directory("csv");
+        let mut recorder =
+            TelemetryRecorder::new(&directory, Format::Csv, RotationPolicy::default()).unwrap();
+        recorder.record_scan(&[1.0, 0.5]).unwrap();
+        recorder.record_scan(&[0.25]).unwrap();
+        drop(recorder);
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 1, "{:?}", files);
+        assert!(files[0].to_string_lossy().ends_with(".csv"));
+        let contents = fs::read_to_string(&files[0]).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "timestamp,ranges");
+        assert!(lines[1].ends_with(",\"[1.0,0.5]\""), "{}", lines[1]);
+        assert!(lines[2].ends_with(",[0.25]"), "{}", lines[2]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn quotes_cells_with_separators() {
+        for text in &["a,b", "say \"hi\"", "two\nlines", "carriage\rreturn"] {
+            let cell = csv_cell(&Value::from(*text));
+            assert!(cell.starts_with('"') && cell.ends_with('"'), "{}", cell);
+        }
+        assert_eq!(csv_cell(&Value::from("say \"hi\"")), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_cell(&Value::from("plain")), "plain");
+        assert_eq!(csv_cell(&Value::Null), "");
+    }
+
+    #[test]
+    fn writes_json_lines_with_timestamp() {
+        let directory = directory("jsonl");
+        let mut recorder =
+            TelemetryRecorder::new(&directory, Format::JsonLines, RotationPolicy::default())
+                .unwrap();
+        recorder.record("count", &3).unwrap();
+        drop(recorder);
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 1);
+        let record: Value =
+            serde_json::from_str(fs::read_to_string(&files[0]).unwrap().trim()).unwrap();
+        assert_eq!(record["value"], 3);
+        assert!(record["timestamp"].as_f64().unwrap() > 0.0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let directory = directory("size");
+        let policy = RotationPolicy {
+            max_bytes: Some(50),
+            max_age: None,
+        };
+        let mut recorder = TelemetryRecorder::new(&directory, Format::Csv, policy).unwrap();
+        for _ in 0..4 {
+            recorder.record_scan(&[1.0; 10]).unwrap();
+        }
+        drop(recorder);
+
+        // The header and one row exceed the limit, so every row starts a new file
+        let files = files(&directory);
+        assert_eq!(files.len(), 4, "{:?}", files);
+        for file in files {
+            assert_eq!(fs::read_to_string(&file).unwrap().lines().count(), 2);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let directory = directory("age");
+        let policy = RotationPolicy {
+            max_bytes: None,
+            max_age: Some(Duration::from_millis(50)),
+        };
+        let mut recorder = TelemetryRecorder::new(&directory, Format::JsonLines, policy).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        recorder.record_at("count", &1, start).unwrap();
+        recorder
+            .record_at("count", &2, start + Duration::from_millis(49))
+            .unwrap();
+        recorder
+            .record_at("count", &3, start + Duration::from_millis(50))
+            .unwrap();
+        drop(recorder);
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 2, "{:?}", files);
+        let lines: Vec<usize> = files
+            .iter()
+            .map(|file| fs::read_to_string(file).unwrap().lines().count())
+            .collect();
+        assert_eq!(lines.iter().sum::<usize>(), 3);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::NeatoRobot;
+
+/// Lifetime counters from `GetWarranty`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Warranty {
+    /// Cumulative cleaning time, in seconds.
+    pub cleaning_seconds: u64,
+    /// Only reported by firmware that prints bare hexadecimal lines.
+    pub cleanings: u64,
+    /// Charge cycles of the battery, only reported by firmware that prints
+    /// `Name,Value` lines.
+    pub battery_cycles: u64,
+    /// Code Neato uses to validate warranty claims.
+    pub validation_code: String,
+}
+
+impl Warranty {
+    pub fn cleaning_hours(&self) -> f32 {
+        self.cleaning_seconds as f32 / 3600.0
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid warranty line {0:?}")]
+pub struct InvalidWarrantyError(String);
+
+impl FromStr for Warranty {
+    type Err = InvalidWarrantyError;
+
+    /// Parse a `GetWarranty` reply. Older firmware prints the cleaning time, the
+    /// number of cleanings and the validation code as bare hexadecimal lines, newer
+    /// firmware prints `CumulativeCleaningTimeInSecs`, `CumulativeBatteryCycles`
+    /// and `ValidationCode` as `Name,Value` lines.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut warranty = Warranty::default();
+        let lines: Vec<&str> = s
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        if lines.iter().any(|line| line.contains(',')) {
+            for line in lines {
+                let invalid = || InvalidWarrantyError(line.to_string());
+                let mut fields = line.splitn(2, ',').map(|field| field.trim());
+                let name = fields.next().unwrap_or("");
+                let value = fields.next().unwrap_or("");
+                match name {
+                    "CumulativeCleaningTimeInSecs" => {
+                        warranty.cleaning_seconds = value.parse().map_err(|_| invalid())?
+                    }
+                    "CumulativeBatteryCycles" => {
+                        warranty.battery_cycles = value.parse().map_err(|_| invalid())?
+                    }
+                    "ValidationCode" => warranty.validation_code = value.to_string(),
+                    _ => log::debug!("Skipping warranty line: {}", line),
+                }
+            }
+        } else {
+            let hex = |index: usize| match lines.get(index) {
+                Some(line) => u64::from_str_radix(line, 16)
+                    .map_err(|_| InvalidWarrantyError(line.to_string())),
+                None => Ok(0),
+            };
+            warranty.cleaning_seconds = hex(0)?;
+            warranty.cleanings = hex(1)?;
+            warranty.validation_code = lines.get(2).unwrap_or(&"").to_string();
+        }
+
+        Ok(warranty)
+    }
+}
+
+/// A part that wears with cleaning time.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Consumable {
+    /// Cleaning hours after which the part should be replaced.
+    pub life_hours: f32,
+    /// Cumulative cleaning hours when the part was last replaced.
+    pub replaced_at_hours: f32,
+}
+
+impl Consumable {
+    pub fn new(life_hours: f32) -> Self {
+        Self {
+            life_hours,
+            replaced_at_hours: 0.0,
+        }
+    }
+
+    /// Fraction of the part's life used, above 1 when it is overdue.
+    pub fn wear(&self, cleaning_hours: f32) -> f32 {
+        (cleaning_hours - self.replaced_at_hours).max(0.0) / self.life_hours
+    }
+
+    /// Cleaning hours until the part should be replaced, negative when overdue.
+    pub fn hours_left(&self, cleaning_hours: f32) -> f32 {
+        self.life_hours - (cleaning_hours - self.replaced_at_hours).max(0.0)
+    }
+}
+
+/// Expected life of the consumables. The defaults follow Neato's advice of a new
+/// filter every one to two months and a new brush every six to twelve, assuming
+/// an hour of cleaning a day.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WearModel {
+    pub brush: Consumable,
+    pub filter: Consumable,
+}
+
+impl Default for WearModel {
+    fn default() -> Self {
+        Self {
+            brush: Consumable::new(270.0),
+            filter: Consumable::new(45.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WearEstimate {
+    /// Fraction of the part's life used, above 1 when it is overdue.
+    pub wear: f32,
+    /// Negative when overdue.
+    pub hours_left: f32,
+}
+
+impl WearEstimate {
+    pub fn is_due(&self) -> bool {
+        self.wear >= 1.0
+    }
+}
+
+/// The lifetime counters with wear estimates for the consumables.
+#[derive(Debug, Clone, Serialize)]
+pub struct Usage {
+    pub warranty: Warranty,
+    pub cleaning_hours: f32,
+    pub brush: WearEstimate,
+    pub filter: WearEstimate,
+}
+
+impl Usage {
+    pub fn new(warranty: Warranty, model: &WearModel) -> Self {
+        let hours = warranty.cleaning_hours();
+        let estimate = |part: &Consumable| WearEstimate {
+            wear: part.wear(hours),
+            hours_left: part.hours_left(hours),
+        };
+        Self {
+            cleaning_hours: hours,
+            brush: estimate(&model.brush),
+            filter: estimate(&model.filter),
+            warranty,
+        }
+    }
+}
+
+/// Read the lifetime counters and estimate the wear of the consumables.
+pub fn get_usage<R: NeatoRobot + ?Sized>(robot: &mut R, model: &WearModel) -> Result<Usage> {
+    Ok(Usage::new(robot.get_warranty()?, model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_value_reply() {
+        let reply = "Parameter,Value\r
+CumulativeCleaningTimeInSecs,360000\r
+CumulativeBatteryCycles,52\r
+ValidationCode,A1B2C3D4\r
+";
+        let warranty: Warranty = reply.parse().unwrap();
+        assert_eq!(
+            warranty,
+            Warranty {
+                cleaning_seconds: 360000,
+                cleanings: 0,
+                battery_cycles: 52,
+                validation_code: String::from("A1B2C3D4"),
+            }
+        );
+        assert_eq!(warranty.cleaning_hours(), 100.0);
+    }
+
+    #[test]
+    fn parses_hexadecimal_reply() {
+        let warranty: Warranty = "00057e40\r\n0034\r\n4527c2e3\r\n".parse().unwrap();
+        assert_eq!(warranty.cleaning_seconds, 360000);
+        assert_eq!(warranty.cleanings, 52);
+        assert_eq!(warranty.battery_cycles, 0);
+        assert_eq!(warranty.validation_code, "4527c2e3");
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        assert!("CumulativeCleaningTimeInSecs,lots\r\n"
+            .parse::<Warranty>()
+            .is_err());
+        assert!("zz\r\n".parse::<Warranty>().is_err());
+    }
+
+    #[test]
+    fn skips_unknown_names() {
+        // Names are matched exactly, not by the words they contain
+        let warranty: Warranty = "TimeOfLastClean,12\r\nCleanMode,3\r\n".parse().unwrap();
+        assert_eq!(warranty, Warranty::default());
+    }
+
+    #[test]
+    fn estimates_wear() {
+        let model = WearModel {
+            brush: Consumable::new(270.0),
+            filter: Consumable {
+                life_hours: 45.0,
+                replaced_at_hours: 80.0,
+            },
+        };
+        let warranty = Warranty {
+            cleaning_seconds: 100 * 3600,
+            ..Default::default()
+        };
+        let usage = Usage::new(warranty, &model);
+
+        assert_eq!(usage.cleaning_hours, 100.0);
+        assert!((usage.brush.wear - 100.0 / 270.0).abs() < 1e-6);
+        assert_eq!(usage.brush.hours_left, 170.0);
+        assert!(!usage.brush.is_due());
+        assert!((usage.filter.wear - 20.0 / 45.0).abs() < 1e-6);
+        assert_eq!(usage.filter.hours_left, 25.0);
+    }
+
+    #[test]
+    fn overdue_part_is_due() {
+        let filter = Consumable::new(45.0);
+        assert_eq!(filter.wear(90.0), 2.0);
+        assert_eq!(filter.hours_left(90.0), -45.0);
+
+        // Replaced after the cleaning time counted so far, such as on a reset robot
+        let brush = Consumable {
+            life_hours: 270.0,
+            replaced_at_hours: 50.0,
+        };
+        assert_eq!(brush.wear(10.0), 0.0);
+        assert_eq!(brush.hours_left(10.0), 270.0);
+
+        let usage = Usage::new(
+            Warranty {
+                cleaning_seconds: 45 * 3600,
+                ..Default::default()
+            },
+            &WearModel::default(),
+        );
+        assert!(usage.filter.is_due());
+    }
+}